@@ -0,0 +1,647 @@
+use crate::{
+    eval::qualify,
+    time::{self, is_time, Mismatch, TimeOp},
+    Agg, Closure, Env, Error, Exp,
+    Exp::*,
+    Function,
+};
+
+use std::{collections::HashMap, fmt};
+
+/// The type of an expression, worked out before evaluating it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Type {
+    Table(Vec<(String, Type)>),
+    Bool,
+    Int,
+    Float,
+    Decimal,
+    Date,
+    Time,
+    Timestamp,
+    Interval,
+    Str,
+    /// A user-defined function, without its captured values, and the types
+    /// of those values. What it returns depends on its arguments, so each
+    /// application checks the body again.
+    Function(Box<Closure>, Vec<(String, Type)>),
+    /// A type that isn't known until evaluation, like a column whose values
+    /// have different types. It's compatible with every other type.
+    #[default]
+    Unknown,
+}
+
+pub type TypeEnv = HashMap<String, Type>;
+
+impl Type {
+    /// The type of a value.
+    pub fn of(exp: &Exp) -> Type {
+        match exp {
+            Table(table) => Type::Table(
+                table
+                    .vars()
+                    .iter()
+                    .enumerate()
+                    .zip(table.columns())
+                    .map(|((i, var), column)| match &column.ty {
+                        Type::Unknown => {
+                            let types = table
+                                .rows()
+                                .iter()
+                                .filter(|row| !row[i].is_null())
+                                .map(|row| Type::of(&row[i]));
+                            (var.clone(), unify_all(types))
+                        }
+                        ty => (var.clone(), ty.clone()),
+                    })
+                    .collect(),
+            ),
+            Bool(_) => Type::Bool,
+            Int(_) => Type::Int,
+            Float(_) => Type::Float,
+            Decimal(_) => Type::Decimal,
+            Date(_) => Type::Date,
+            Time(_) => Type::Time,
+            Timestamp(_) => Type::Timestamp,
+            Interval(_) => Type::Interval,
+            Str(_) => Type::Str,
+            Exp::Closure(closure) => Type::function(closure, vec![]),
+            Spanned(_, exp) => Type::of(exp),
+            _ => Type::Unknown,
+        }
+    }
+
+    /// The type of a function, with the types of the variables it captures
+    /// besides its own.
+    fn function(closure: &Closure, captured: Vec<(String, Type)>) -> Type {
+        let mut types = closure
+            .env
+            .iter()
+            .map(|(var, exp)| (var.clone(), Type::of(exp)))
+            .chain(captured)
+            .collect::<Vec<_>>();
+        types.sort_by(|(l, _), (r, _)| l.cmp(r));
+        let closure = Closure {
+            env: vec![],
+            ..closure.clone()
+        };
+        Type::Function(Box::new(closure), types)
+    }
+
+    /// The type a column declares in a table header, like `id int`.
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "int" => Some(Type::Int),
+            "float" => Some(Type::Float),
+            "decimal" => Some(Type::Decimal),
+            "date" => Some(Type::Date),
+            "time" => Some(Type::Time),
+            "timestamp" => Some(Type::Timestamp),
+            "interval" => Some(Type::Interval),
+            "str" => Some(Type::Str),
+            "bool" => Some(Type::Bool),
+            "any" => Some(Type::Unknown),
+            _ => None,
+        }
+    }
+
+    /// The name a table header uses for the type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Table(_) => "table",
+            Type::Bool => "bool",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Decimal => "decimal",
+            Type::Date => "date",
+            Type::Time => "time",
+            Type::Timestamp => "timestamp",
+            Type::Interval => "interval",
+            Type::Str => "str",
+            Type::Function(..) => "function",
+            Type::Unknown => "any",
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Type::Table(_) => "table",
+            Type::Bool => "boolean",
+            Type::Int => "integer",
+            Type::Float => "float",
+            Type::Decimal => "decimal",
+            Type::Date => "date",
+            Type::Time => "time",
+            Type::Timestamp => "timestamp",
+            Type::Interval => "interval",
+            Type::Str => "string",
+            Type::Function(..) => "function",
+            Type::Unknown => "expression",
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Type::Int | Type::Float | Type::Decimal)
+    }
+
+    /// Whether a value of this type could be used where `other` is expected.
+    pub fn matches(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (l, r) => l.describe() == r.describe(),
+        }
+    }
+
+    fn unify(self, other: Type) -> Type {
+        match (self, other) {
+            (Type::Table(l), Type::Table(r))
+                if l.iter()
+                    .map(|(var, _)| var)
+                    .eq(r.iter().map(|(var, _)| var)) =>
+            {
+                Type::Table(
+                    l.into_iter()
+                        .zip(r)
+                        .map(|((var, l), (_, r))| (var, l.unify(r)))
+                        .collect(),
+                )
+            }
+            (l, r) if l == r => l,
+            _ => Type::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Table(columns) => {
+                let columns = columns
+                    .iter()
+                    .map(|(var, ty)| match ty {
+                        Type::Unknown => var.clone(),
+                        ty => format!("{} {}", var, ty),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "table ({})", columns.join(", "))
+            }
+            ty => write!(f, "{}", ty.describe()),
+        }
+    }
+}
+
+fn unify_all(types: impl IntoIterator<Item = Type>) -> Type {
+    let mut types = types.into_iter();
+    match types.next() {
+        Some(first) => types.fold(first, Type::unify),
+        None => Type::Unknown,
+    }
+}
+
+/// The types of the values in an environment.
+pub fn type_env(env: &Env) -> TypeEnv {
+    env.iter()
+        .map(|(var, exp)| (var.clone(), Type::of(exp)))
+        .collect()
+}
+
+/// Check that an expression is well typed, returning its type. The errors
+/// are the ones evaluation would report, found without evaluating anything.
+pub fn check(exp: &Exp, env: &TypeEnv) -> Result<Type, Error> {
+    match exp {
+        Let(var, exp, body) => {
+            let ty = check(exp, env)?;
+            let mut env = env.clone();
+            env.insert(var.clone(), ty);
+            check(body, &env)
+        }
+        Select(vars, table) => map_table(table, env, |columns| {
+            let columns = vars
+                .iter()
+                .map(|var| Ok((var.clone(), column(&columns, var)?.clone())))
+                .collect::<Result<_, Error>>()?;
+            Ok(Type::Table(columns))
+        }),
+        Rename(renames, table) => map_table(table, env, |mut columns| {
+            let vars = columns
+                .iter()
+                .map(|(var, _)| var.clone())
+                .collect::<Vec<_>>();
+            for (i, (old, new)) in renames.iter().enumerate() {
+                if renames[..i].iter().any(|(o, _)| o == old) {
+                    return Err(Error::DuplicateColumn(old.clone()));
+                }
+                match vars.iter().position(|var| var == old) {
+                    Some(j) => columns[j].0 = new.clone(),
+                    None => return Err(Error::UnknownColumn(old.clone())),
+                }
+            }
+            distinct(columns)
+        }),
+        Extend(new, table) => map_table(table, env, |mut columns| {
+            let vars = names(&columns)
+                .into_iter()
+                .chain(new.iter().map(|(var, _)| var.clone()));
+            distinct(vars.map(|var| (var, Type::Unknown)).collect())?;
+            for (var, exp) in new {
+                let ty = check(exp, &row_env(env, &columns))?;
+                columns.push((var.clone(), ty));
+            }
+            Ok(Type::Table(columns))
+        }),
+        Group(keys, aggs, table) => map_table(table, env, |columns| {
+            let mut result = keys
+                .iter()
+                .map(|key| Ok((key.clone(), column(&columns, key)?.clone())))
+                .collect::<Result<Vec<_>, Error>>()?;
+            for agg in aggs {
+                let ty = match (agg, agg.column().map(|var| column(&columns, var))) {
+                    (Agg::Count, _) => Type::Int,
+                    (Agg::Sum(_) | Agg::Avg(_), Some(ty)) => {
+                        let ty = ty?;
+                        if !ty.is_number() && *ty != Type::Unknown {
+                            return Err(mismatch("number", ty));
+                        }
+                        ty.clone()
+                    }
+                    (_, Some(ty)) => {
+                        let ty = ty?;
+                        if !comparable(ty, ty) {
+                            return Err(mismatch("number, string or boolean", ty));
+                        }
+                        ty.clone()
+                    }
+                    (_, None) => Type::Unknown,
+                };
+                result.push((agg.name(), ty));
+            }
+            distinct(result)
+        }),
+        Where(table, cond) => map_table(table, env, |columns| {
+            expect(cond, &row_env(env, &columns), &Type::Bool)?;
+            Ok(Type::Table(columns))
+        }),
+        Union(l, r) => match check_operands(l, r, env)? {
+            Operands::Tables(l, r) => {
+                let (l_vars, r_vars) = (names(&l), names(&r));
+                if l_vars != r_vars {
+                    return Err(column_mismatch("union", l_vars, r_vars));
+                }
+                Ok(Type::Table(l).unify(Type::Table(r)))
+            }
+            Operands::Numbers(ty) => Ok(ty),
+            Operands::Times(l_ty, r_ty) => check_time(TimeOp::Add, &l_ty, &r_ty, l, r),
+            Operands::Unknown => Ok(Type::Unknown),
+        },
+        Difference(l, r) => match check_operands(l, r, env)? {
+            Operands::Tables(l, r) => {
+                let (l_vars, r_vars) = (names(&l), names(&r));
+                if l_vars != r_vars {
+                    return Err(column_mismatch("difference", l_vars, r_vars));
+                }
+                Ok(Type::Table(l))
+            }
+            Operands::Numbers(ty) => Ok(ty),
+            Operands::Times(l_ty, r_ty) => check_time(TimeOp::Sub, &l_ty, &r_ty, l, r),
+            Operands::Unknown => Ok(Type::Unknown),
+        },
+        Product(l_exp, r_exp) => match check_operands(l_exp, r_exp, env)? {
+            Operands::Tables(l, r) => {
+                let (vars, _) = qualify(l_exp, &names(&l), r_exp, &names(&r));
+                let types = l.into_iter().chain(r).map(|(_, ty)| ty);
+                distinct(vars.into_iter().zip(types).collect())
+            }
+            Operands::Numbers(ty) => Ok(ty),
+            Operands::Times(l_ty, r_ty) => check_time(TimeOp::Mul, &l_ty, &r_ty, l_exp, r_exp),
+            Operands::Unknown => Ok(Type::Unknown),
+        },
+        Divide(l, r) => {
+            let (l, r) = (check_number(l, env)?, check_number(r, env)?);
+            Ok(widen(&l, &r))
+        }
+        Join(l, r) => match (check_table(l, env)?, check_table(r, env)?) {
+            (Some(l), Some(r)) => {
+                let mut columns = l.clone();
+                for (var, ty) in r {
+                    if column(&l, &var).is_err() {
+                        columns.push((var, ty));
+                    }
+                }
+                Ok(Type::Table(columns))
+            }
+            _ => Ok(Type::Unknown),
+        },
+        JoinOn(l_exp, r_exp, cond) => {
+            match (check_table(l_exp, env)?, check_table(r_exp, env)?) {
+                (Some(l), Some(r)) => {
+                    let (vars, names) = qualify(l_exp, &names(&l), r_exp, &names(&r));
+                    let types = l.into_iter().chain(r).map(|(_, ty)| ty).collect::<Vec<_>>();
+                    let mut cond_env = env.clone();
+                    cond_env.extend(names.iter().zip(&types).flat_map(|(names, ty)| {
+                        names.iter().map(|name| (name.clone(), ty.clone()))
+                    }));
+                    expect(cond, &cond_env, &Type::Bool)?;
+                    distinct(vars.into_iter().zip(types).collect())
+                }
+                _ => Ok(Type::Unknown),
+            }
+        }
+        Table(table) => {
+            let mut columns = table
+                .vars()
+                .iter()
+                .cloned()
+                .zip(table.columns().iter().map(|column| column.ty.clone()))
+                .collect::<Vec<_>>();
+            let mut found = vec![vec![]; columns.len()];
+            for row in table.rows() {
+                for ((exp, (var, declared)), found) in row.iter().zip(&columns).zip(&mut found) {
+                    let ty = check(exp, env)?;
+                    if !ty.matches(declared) {
+                        let expected = format!("{} in column `{}`", declared.describe(), var);
+                        return Err(mismatch(&expected, &ty).at_exp(exp));
+                    }
+                    if !exp.is_null() {
+                        found.push(ty);
+                    }
+                }
+            }
+            for ((_, ty), found) in columns.iter_mut().zip(found) {
+                if *ty == Type::Unknown {
+                    *ty = unify_all(found);
+                }
+            }
+            Ok(Type::Table(columns))
+        }
+        Or(l, r) | And(l, r) => {
+            expect(l, env, &Type::Bool)?;
+            expect(r, env, &Type::Bool)?;
+            Ok(Type::Bool)
+        }
+        Equals(l, r) => {
+            check(l, env)?;
+            check(r, env)?;
+            Ok(Type::Bool)
+        }
+        Less(l, r) | LessEquals(l, r) | Greater(l, r) | GreaterEquals(l, r) => {
+            let (l_ty, r_ty) = (check(l, env)?, check(r, env)?);
+            if !comparable(&l_ty, &l_ty) {
+                return Err(mismatch("number, string or boolean", &l_ty).at_exp(l));
+            }
+            if !comparable(&l_ty, &r_ty) {
+                return Err(mismatch(l_ty.describe(), &r_ty).at_exp(r));
+            }
+            Ok(Type::Bool)
+        }
+        Not(exp) => {
+            expect(exp, env, &Type::Bool)?;
+            Ok(Type::Bool)
+        }
+        IsNull(exp) => {
+            check(exp, env)?;
+            Ok(Type::Bool)
+        }
+        Like(l, r) => {
+            expect(l, env, &Type::Str)?;
+            expect(r, env, &Type::Str)?;
+            Ok(Type::Bool)
+        }
+        Call(f, args) => {
+            let (params, result) = f.signature();
+            if args.len() != params.len() {
+                return Err(arity(*f, args));
+            }
+            for (arg, param) in args.iter().zip(&params) {
+                expect(arg, env, param)?;
+            }
+            Ok(result)
+        }
+        Schema(table) => {
+            check_table(table, env)?;
+            Ok(schema_type())
+        }
+        // Null can stand in for a value of any type
+        Null => Ok(Type::Unknown),
+        Bool(_) => Ok(Type::Bool),
+        Int(_) => Ok(Type::Int),
+        Float(_) => Ok(Type::Float),
+        Decimal(_) => Ok(Type::Decimal),
+        Date(_) => Ok(Type::Date),
+        Time(_) => Ok(Type::Time),
+        Timestamp(_) => Ok(Type::Timestamp),
+        Interval(_) => Ok(Type::Interval),
+        Str(_) => Ok(Type::Str),
+        Exp::Closure(closure) => {
+            // Check the body with arguments of any type, to find the mistakes
+            // that don't depend on them
+            let mut body_env = env.clone();
+            body_env.extend(closure.params.iter().map(|p| (p.clone(), Type::Unknown)));
+            check(&closure.body, &body_env)?;
+            let captured = closure
+                .captures()
+                .into_iter()
+                .filter_map(|var| env.get(&var).map(|ty| (var, ty.clone())))
+                .collect();
+            Ok(Type::function(closure, captured))
+        }
+        Apply(f, args) => {
+            let ty = match f.unspanned() {
+                Var(var) if !env.contains_key(var) => {
+                    return Err(Error::UnknownFunction(var.clone()))
+                }
+                _ => check(f, env)?,
+            };
+            match ty {
+                Type::Function(closure, captured) => {
+                    if args.len() != closure.params.len() {
+                        return Err(Error::Arity {
+                            function: closure.name,
+                            expected: closure.params.len(),
+                            found: args.len(),
+                        });
+                    }
+                    let mut body_env = captured.into_iter().collect::<TypeEnv>();
+                    for (param, arg) in closure.params.into_iter().zip(args) {
+                        body_env.insert(param, check(arg, env)?);
+                    }
+                    check(&closure.body, &body_env)
+                }
+                Type::Unknown => {
+                    for arg in args {
+                        check(arg, env)?;
+                    }
+                    Ok(Type::Unknown)
+                }
+                found => Err(mismatch("function", &found).at_exp(f)),
+            }
+        }
+        Var(var) => match env.get(var) {
+            Some(ty) => Ok(ty.clone()),
+            None => Err(Error::UnknownVariable(var.clone())),
+        },
+        Exp::Version(var, version) => {
+            let var = version.name(var);
+            match env.get(&var) {
+                Some(ty) => Ok(ty.clone()),
+                None => Err(Error::UnknownVariable(var)),
+            }
+        }
+        Spanned(span, exp) => check(exp, env).map_err(|e| e.at(*span)),
+    }
+}
+
+fn arity(f: Function, args: &[Exp]) -> Error {
+    Error::Arity {
+        function: f.name().to_string(),
+        expected: f.arity(),
+        found: args.len(),
+    }
+}
+
+/// The type of the table `schema` returns.
+pub(crate) fn schema_type() -> Type {
+    Type::Table(vec![
+        ("column".to_string(), Type::Str),
+        ("type".to_string(), Type::Str),
+        ("key".to_string(), Type::Bool),
+        ("unique".to_string(), Type::Bool),
+        ("references".to_string(), Type::Str),
+    ])
+}
+
+/// Check a table operand, returning `None` if its columns aren't known.
+fn check_table(exp: &Exp, env: &TypeEnv) -> Result<Option<Vec<(String, Type)>>, Error> {
+    match check(exp, env)? {
+        Type::Table(columns) => Ok(Some(columns)),
+        Type::Unknown => Ok(None),
+        found => Err(mismatch("table", &found).at_exp(exp)),
+    }
+}
+
+/// Check a table operand and work out the result's type from its columns.
+fn map_table(
+    exp: &Exp,
+    env: &TypeEnv,
+    f: impl FnOnce(Vec<(String, Type)>) -> Result<Type, Error>,
+) -> Result<Type, Error> {
+    match check_table(exp, env)? {
+        Some(columns) => f(columns),
+        None => Ok(Type::Unknown),
+    }
+}
+
+enum Operands {
+    Tables(Vec<(String, Type)>, Vec<(String, Type)>),
+    /// Two numbers, and the type of the result.
+    Numbers(Type),
+    /// Two operands where either is a date, time or interval.
+    Times(Type, Type),
+    Unknown,
+}
+
+fn check_operands(l: &Exp, r: &Exp, env: &TypeEnv) -> Result<Operands, Error> {
+    match (check(l, env)?, check(r, env)?) {
+        (Type::Table(l), Type::Table(r)) => Ok(Operands::Tables(l, r)),
+        (l, r) if l.is_number() && r.is_number() => Ok(Operands::Numbers(widen(&l, &r))),
+        (Type::Unknown, Type::Table(_) | Type::Unknown) | (Type::Table(_), Type::Unknown) => {
+            Ok(Operands::Unknown)
+        }
+        (Type::Unknown, ty) | (ty, Type::Unknown) if ty.is_number() || is_time(&ty) => {
+            Ok(Operands::Unknown)
+        }
+        (Type::Table(_), found) => Err(mismatch("table", &found).at_exp(r)),
+        (l, r) if is_time(&l) || is_time(&r) => Ok(Operands::Times(l, r)),
+        (ty, found) if ty.is_number() => Err(mismatch("number", &found).at_exp(r)),
+        (found, _) => Err(mismatch("table or number", &found).at_exp(l)),
+    }
+}
+
+fn check_time(op: TimeOp, l_ty: &Type, r_ty: &Type, l: &Exp, r: &Exp) -> Result<Type, Error> {
+    time::result_type(op, l_ty, r_ty).map_err(|m| match m {
+        Mismatch::Left => mismatch("table or number", l_ty).at_exp(l),
+        Mismatch::Right(expected) => mismatch(expected, r_ty).at_exp(r),
+    })
+}
+
+fn expect(exp: &Exp, env: &TypeEnv, expected: &Type) -> Result<(), Error> {
+    let ty = check(exp, env)?;
+    expect_type(&ty, expected).map_err(|e| e.at_exp(exp))
+}
+
+fn expect_type(ty: &Type, expected: &Type) -> Result<(), Error> {
+    if ty.matches(expected) {
+        Ok(())
+    } else {
+        Err(mismatch(expected.describe(), ty))
+    }
+}
+
+/// Check a number, or an expression whose type isn't known.
+fn check_number(exp: &Exp, env: &TypeEnv) -> Result<Type, Error> {
+    match check(exp, env)? {
+        ty if ty.is_number() || ty == Type::Unknown => Ok(ty),
+        found => Err(mismatch("number", &found).at_exp(exp)),
+    }
+}
+
+/// The type of arithmetic on two numbers, widened like evaluation does.
+fn widen(l: &Type, r: &Type) -> Type {
+    match (l, r) {
+        (Type::Unknown, _) | (_, Type::Unknown) => Type::Unknown,
+        (Type::Int, Type::Int) => Type::Int,
+        (Type::Float, _) | (_, Type::Float) => Type::Float,
+        _ => Type::Decimal,
+    }
+}
+
+fn comparable(l: &Type, r: &Type) -> bool {
+    match (l, r) {
+        (Type::Table(_), _) | (_, Type::Table(_)) => false,
+        (l, r) if l.is_number() && r.is_number() => true,
+        (l, r) => l.matches(r),
+    }
+}
+
+fn column<'a>(columns: &'a [(String, Type)], var: &str) -> Result<&'a Type, Error> {
+    columns
+        .iter()
+        .find(|(v, _)| v == var)
+        .map(|(_, ty)| ty)
+        .ok_or_else(|| Error::UnknownColumn(var.to_string()))
+}
+
+fn distinct(columns: Vec<(String, Type)>) -> Result<Type, Error> {
+    for (i, (var, _)) in columns.iter().enumerate() {
+        if columns[..i].iter().any(|(v, _)| v == var) {
+            return Err(Error::DuplicateColumn(var.clone()));
+        }
+    }
+    Ok(Type::Table(columns))
+}
+
+/// The environment of a row expression, where columns hide variables of
+/// the same name.
+fn row_env(env: &TypeEnv, columns: &[(String, Type)]) -> TypeEnv {
+    let mut env = env.clone();
+    env.extend(columns.iter().cloned());
+    env
+}
+
+fn names(columns: &[(String, Type)]) -> Vec<String> {
+    columns.iter().map(|(var, _)| var.clone()).collect()
+}
+
+fn mismatch(expected: &str, found: &Type) -> Error {
+    Error::TypeMismatch {
+        expected: expected.to_string(),
+        found: found.describe().to_string(),
+    }
+}
+
+fn column_mismatch(op: &str, left: Vec<String>, right: Vec<String>) -> Error {
+    Error::ColumnMismatch {
+        op: op.to_string(),
+        left,
+        right,
+    }
+}
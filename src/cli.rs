@@ -6,8 +6,13 @@ use clap::Parser;
 pub enum Cli {
     /// Run an expression
     Run(Client),
+    /// Check an expression for type errors without running it, locally and
+    /// with no variables defined
+    Check(Check),
     /// Start the database server
     Start(Server),
+    /// Start an interactive session
+    Repl(Repl),
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -22,6 +27,21 @@ pub struct Client {
     /// Send expression to a running server
     #[arg(short, long, value_name = "URL")]
     pub server: Option<String>,
+
+    /// Keep repeated rows in tables instead of removing them. A server
+    /// decides this when it starts, so it can't be combined with `--server`
+    #[arg(long, conflicts_with = "server")]
+    pub bag: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Check {
+    /// Expression or file containing expression
+    pub target: String,
+
+    /// Interpret target as expression rather than file
+    #[arg(short, long)]
+    pub expression: bool,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -37,4 +57,15 @@ pub struct Server {
     /// Log connections to stdout
     #[arg(short, long, global = true)]
     pub verbose: bool,
+
+    /// Keep repeated rows in tables instead of removing them
+    #[arg(long)]
+    pub bag: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Repl {
+    /// Send input to a running server
+    #[arg(short, long, value_name = "URL")]
+    pub server: Option<String>,
 }
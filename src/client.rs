@@ -1,10 +1,12 @@
+use crate::Response;
+
 use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
 };
 
 #[tokio::main]
-pub async fn client(text: &str, url: &str) -> io::Result<String> {
+pub async fn client(text: &str, url: &str) -> io::Result<Response> {
     let mut stream = TcpStream::connect(url).await?;
 
     stream.write_all(text.as_bytes()).await?;
@@ -13,5 +15,5 @@ pub async fn client(text: &str, url: &str) -> io::Result<String> {
     let mut response = String::new();
     stream.read_to_string(&mut response).await?;
 
-    Ok(response)
+    Ok(Response::decode(&response))
 }
@@ -0,0 +1,217 @@
+use crate::{serialise, Exp};
+
+use std::{fmt, io};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The 1-based line and column of the start of the span. A span from
+    /// other source is clamped to the nearest character in this one.
+    pub fn location(&self, source: &str) -> (usize, usize) {
+        let before = &source[..source.floor_char_boundary(self.start)];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        (line, column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(String),
+    UnknownVariable(String),
+    UnknownFunction(String),
+    /// A function applied to the wrong number of arguments.
+    Arity {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    InvalidName(String),
+    UnknownColumn(String),
+    DuplicateColumn(String),
+    TypeMismatch {
+        expected: String,
+        found: String,
+    },
+    ColumnMismatch {
+        op: String,
+        left: Vec<String>,
+        right: Vec<String>,
+    },
+    RowLength {
+        columns: Vec<String>,
+        found: usize,
+    },
+    /// Two rows share the values of a key: each column and the value both
+    /// rows have in it.
+    DuplicateKey(Vec<(String, Exp)>),
+    /// A value of a column isn't in the column it references.
+    MissingReference {
+        column: String,
+        value: Exp,
+        target: String,
+    },
+    Arithmetic(String),
+    Io(String),
+    At(Span, Box<Error>),
+}
+
+impl Error {
+    pub fn parse(message: &str) -> Error {
+        Error::Parse(message.to_string())
+    }
+
+    pub fn type_mismatch(expected: &str, found: &Exp) -> Error {
+        Error::TypeMismatch {
+            expected: expected.to_string(),
+            found: found.describe().to_string(),
+        }
+    }
+
+    /// Attach a span, unless the error already has a more precise one.
+    pub fn at(self, span: Span) -> Error {
+        match self {
+            Error::At(..) => self,
+            error => Error::At(span, Box::new(error)),
+        }
+    }
+
+    /// Attach the span of an expression, if it has one.
+    pub fn at_exp(self, exp: &Exp) -> Error {
+        match exp {
+            Exp::Spanned(span, _) => self.at(*span),
+            _ => self,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::At(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Parse(_) => "parse",
+            Error::UnknownVariable(_) => "name",
+            Error::UnknownFunction(_) => "name",
+            Error::Arity { .. } => "type",
+            Error::InvalidName(_) => "name",
+            Error::UnknownColumn(_) => "column",
+            Error::DuplicateColumn(_) => "column",
+            Error::TypeMismatch { .. } => "type",
+            Error::ColumnMismatch { .. } => "column",
+            Error::RowLength { .. } => "column",
+            Error::DuplicateKey(_) => "constraint",
+            Error::MissingReference { .. } => "constraint",
+            Error::Arithmetic(_) => "arithmetic",
+            Error::Io(_) => "io",
+            Error::At(_, error) => error.category(),
+        }
+    }
+
+    /// Render the error with the offending source line and a caret underline.
+    pub fn render(&self, source: &str) -> String {
+        let Some(span) = self.span() else {
+            return format!("error[{}]: {}", self.category(), self);
+        };
+        let (line, column) = span.location(source);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let width = source
+            .get(span.start..span.end)
+            .map(|s| s.lines().next().unwrap_or("").chars().count())
+            .unwrap_or(0)
+            .max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "error[{}]: {}\n{}--> line {}, column {}\n{} |\n{} | {}\n{} | {}{}",
+            self.category(),
+            self,
+            gutter,
+            line,
+            column,
+            gutter,
+            line,
+            text,
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "{}", message),
+            Error::UnknownVariable(var) => write!(f, "variable `{}` not defined", var),
+            Error::UnknownFunction(var) => write!(f, "unknown function `{}`", var),
+            Error::Arity {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} argument{}, found {}",
+                function,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found
+            ),
+            Error::InvalidName(var) => write!(f, "`{}` can't be used as a variable name", var),
+            Error::UnknownColumn(var) => write!(f, "no column named `{}`", var),
+            Error::DuplicateColumn(var) => write!(f, "more than one column named `{}`", var),
+            Error::TypeMismatch { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            Error::ColumnMismatch { op, left, right } => write!(
+                f,
+                "expected tables with matching columns in {}, found ({}) and ({})",
+                op,
+                left.join(", "),
+                right.join(", ")
+            ),
+            Error::RowLength { columns, found } => write!(
+                f,
+                "expected {} values in each row of ({}), found a row of {}",
+                columns.len(),
+                columns.join(", "),
+                found
+            ),
+            Error::DuplicateKey(key) => {
+                let values = key
+                    .iter()
+                    .map(|(var, exp)| format!("`{}` = {}", var, serialise(exp.clone())))
+                    .collect::<Vec<_>>();
+                write!(f, "more than one row with {}", values.join(" and "))
+            }
+            Error::MissingReference {
+                column,
+                value,
+                target,
+            } => write!(
+                f,
+                "`{}` refers to {}, which isn't a value of `{}`",
+                column,
+                serialise(value.clone()),
+                target
+            ),
+            Error::Arithmetic(message) => write!(f, "{}", message),
+            Error::Io(message) => write!(f, "{}", message),
+            Error::At(_, error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e.to_string())
+    }
+}
@@ -1,155 +1,898 @@
-use crate::{Exp, Exp::*};
+use crate::{
+    exp::analyse_reads,
+    time::{self, Mismatch, TimeOp},
+    Agg, Column, Error, Exp,
+    Exp::*,
+    Function, Key, Relation, Type, F64,
+};
 
-use std::{cmp::max, collections::HashMap};
+use chrono::Utc;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
 
 pub type Env = HashMap<String, Exp>;
 
-pub fn eval(exp: &Exp, env: &Env) -> Result<(Exp, Env), String> {
+/// Whether tables are sets, with repeated rows removed, or bags that keep
+/// them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Semantics {
+    #[default]
+    Set,
+    Bag,
+}
+
+pub fn eval(exp: &Exp, env: &Env) -> Result<(Exp, Env), Error> {
+    eval_with(exp, env, Semantics::Set)
+}
+
+pub fn eval_with(exp: &Exp, env: &Env, sem: Semantics) -> Result<(Exp, Env), Error> {
     match exp {
         Let(var, exp, body) => {
-            let (exp, _) = eval(exp, env)?;
+            let (exp, _) = eval_with(exp, env, sem)?;
             let mut env = env.clone();
             env.insert(var.clone(), exp);
-            eval(body, &env)
+            eval_with(body, &env, sem)
         }
         Select(select_vars, table) => {
-            let (Table(table_vars, exps), _) = eval(table, env)? else {
-                return Err("expected table".to_string());
-            };
-            let var_indices = table_vars
+            let table = eval_table(table, env, sem)?;
+            let indices = select_vars
                 .iter()
-                .enumerate()
-                .map(|(i, s)| (s, i))
-                .collect::<HashMap<_, _>>();
-            let keep_indices = select_vars
+                .map(|var| table.index(var))
+                .collect::<Result<Vec<_>, Error>>()?;
+            let rows = table
+                .rows()
                 .iter()
-                .filter_map(|k| var_indices.get(k))
-                .cloned()
-                .collect::<Vec<_>>();
-            let exps = exps
-                .chunks(max(table_vars.len(), 1))
-                .flat_map(|row| keep_indices.iter().filter_map(|&i| row.get(i).cloned()))
+                .map(|row| indices.iter().map(|&i| row[i].clone()).collect())
                 .collect();
-            Ok((Table(select_vars.clone(), exps), env.clone()))
+            let rows = distinct(rows, sem);
+            // Keys may not hold once some columns are dropped, so only types are kept
+            let columns = indices
+                .iter()
+                .map(|&i| Column::typed(table.columns()[i].ty.clone()))
+                .collect();
+            let table = Relation::new(select_vars.clone(), rows)?.declare(columns)?;
+            Ok((Table(table), env.clone()))
         }
-        Where(table, cond) => {
-            let (Table(vars, exps), _) = eval(table, env)? else {
-                return Err("expected table".to_string());
-            };
-            let exps = exps
-                .chunks(max(vars.len(), 1))
-                .try_fold(vec![], |mut acc, exps| {
-                    let env = vars
-                        .iter()
-                        .zip(exps)
-                        .map(|(var, exp)| (var.clone(), exp.clone()))
-                        .collect();
-
-                    match eval(cond, &env)? {
-                        (Bool(true), _) => {
-                            acc.extend_from_slice(exps);
-                            Ok(acc)
-                        }
-                        (Bool(false), _) => Ok(acc),
-                        _ => Err("expected boolean in where clause".to_string()),
-                    }
-                })?;
-            Ok((Table(vars, exps), env.clone()))
+        Rename(renames, table) => {
+            let table = eval_table(table, env, sem)?;
+            let mut renamed = table.vars().to_vec();
+            for (i, (old, new)) in renames.iter().enumerate() {
+                if renames[..i].iter().any(|(o, _)| o == old) {
+                    return Err(Error::DuplicateColumn(old.clone()));
+                }
+                renamed[table.index(old)?] = new.clone();
+            }
+            let columns = table.columns().to_vec();
+            let (_, rows) = table.into_parts();
+            let table = Relation::new(renamed, rows)?.declare(columns)?;
+            Ok((Table(table), env.clone()))
         }
-        Union(l, r) => {
-            let (Table(vars, mut exps), _) = eval(l, env)? else {
-                return Err("expected table".to_string());
-            };
-            let (Table(r_vars, mut r_exps), _) = eval(r, env)? else {
-                return Err("expected table".to_string());
-            };
-            if vars != r_vars {
-                return Err("expected tables with matching columns in union".to_string());
+        Extend(new, table) => {
+            let table = eval_table(table, env, sem)?;
+            let mut columns = table.columns().to_vec();
+            columns.resize(columns.len() + new.len(), Column::default());
+            let (mut vars, rows) = table.into_parts();
+            for (var, _) in new {
+                if vars.contains(var) {
+                    return Err(Error::DuplicateColumn(var.clone()));
+                }
+                vars.push(var.clone());
+            }
+            let outer = outer_env(new.iter().map(|(_, exp)| exp), env);
+            let mut extended = vec![];
+            for mut row in rows {
+                for (_, exp) in new {
+                    let (value, _) = eval_with(exp, &row_env(&outer, vars.iter().zip(&row)), sem)?;
+                    row.push(value);
+                }
+                extended.push(row);
             }
-            exps.append(&mut r_exps);
-            Ok((Table(vars, exps), env.clone()))
+            let table = Relation::new(vars, extended)?.declare(columns)?;
+            Ok((Table(table), env.clone()))
         }
-        Difference(l, r) => {
-            let (Table(l_vars, l_exps), _) = eval(l, env)? else {
-                return Err("expected table".to_string());
-            };
-            let (Table(r_vars, r_exps), _) = eval(r, env)? else {
-                return Err("expected table".to_string());
-            };
-            if l_vars != r_vars {
-                return Err("expected tables with matching columns in difference".to_string());
-            }
-            let vars = l_vars;
-            let exps = l_exps
-                .chunks(max(vars.len(), 1))
-                .filter(|&l_exp| {
-                    r_exps
-                        .chunks(max(vars.len(), 1))
-                        .all(|r_exp| l_exp != r_exp)
+        Group(keys, aggs, table) => {
+            let table = eval_table(table, env, sem)?;
+            let key_indices = keys
+                .iter()
+                .map(|key| table.index(key))
+                .collect::<Result<Vec<_>, Error>>()?;
+            let agg_indices = aggs
+                .iter()
+                .map(|agg| agg.column().map(|var| table.index(var)).transpose())
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            // Without keys the whole table is one group, even when it's empty.
+            // Groups are kept in the order their first rows appear.
+            let mut groups: Vec<(&[Exp], Vec<&[Exp]>)> = vec![];
+            let mut indices: HashMap<Vec<Value>, usize> = HashMap::new();
+            if keys.is_empty() {
+                groups.push((&[], vec![]));
+                indices.insert(vec![], 0);
+            }
+            for row in table.rows() {
+                let key = key_indices.iter().map(|&i| Value(&row[i])).collect();
+                let i = *indices.entry(key).or_insert_with(|| {
+                    groups.push((row, vec![]));
+                    groups.len() - 1
+                });
+                groups[i].1.push(row);
+            }
+
+            let rows = groups
+                .into_iter()
+                .map(|(first, rows)| {
+                    let key = key_indices.iter().map(|&i| first[i].clone()).collect();
+                    let values = aggs
+                        .iter()
+                        .zip(&agg_indices)
+                        .map(|(agg, &i)| aggregate(agg, i, &rows))
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    Ok([key, values].concat())
                 })
-                .flat_map(|chunk| chunk.to_vec())
-                .collect();
-            Ok((Table(vars, exps), env.clone()))
+                .collect::<Result<Vec<_>, Error>>()?;
+            let vars = keys.iter().cloned().chain(aggs.iter().map(Agg::name));
+            Ok((Table(Relation::new(vars.collect(), rows)?), env.clone()))
         }
-        Product(l, r) => {
-            let (Table(l_vars, l_exps), _) = eval(l, env)? else {
-                return Err("expected table".to_string());
-            };
-            let (Table(r_vars, r_exps), _) = eval(r, env)? else {
-                return Err("expected table".to_string());
+        Where(table, cond) => {
+            let table = eval_table(table, env, sem)?;
+            let columns = table.columns().to_vec();
+            let (vars, rows) = table.into_parts();
+            let outer = outer_env([cond.as_ref()], env);
+            let mut kept = vec![];
+            for row in rows {
+                if test(cond, &row_env(&outer, vars.iter().zip(&row)), sem)? {
+                    kept.push(row);
+                }
+            }
+            let table = Relation::new(vars, kept)?.declare(columns)?;
+            Ok((Table(table), env.clone()))
+        }
+        Union(l, r) => match eval_operands(l, r, env, sem)? {
+            Operands::Numbers(n) => Ok((
+                n.apply(i64::checked_add, Decimal::checked_add, |l, r| l + r)?,
+                env.clone(),
+            )),
+            Operands::Times(l_val, r_val) => {
+                Ok((eval_time(TimeOp::Add, l_val, r_val, l, r)?, env.clone()))
+            }
+            Operands::Null => Ok((Null, env.clone())),
+            Operands::Tables(l, r) => {
+                if l.vars() != r.vars() {
+                    return Err(column_mismatch("union", l, r));
+                }
+                // Each column keeps what either side declares for it
+                let columns = l
+                    .columns()
+                    .iter()
+                    .zip(r.columns())
+                    .map(|(l, r)| l.or(r))
+                    .collect();
+                let (vars, mut rows) = l.into_parts();
+                rows.extend(r.into_parts().1);
+                let rows = distinct(rows, sem);
+                let table = Relation::new(vars, rows)?.declare(columns)?;
+                Ok((Table(table), env.clone()))
+            }
+        },
+        Difference(l, r) => match eval_operands(l, r, env, sem)? {
+            Operands::Numbers(n) => Ok((
+                n.apply(i64::checked_sub, Decimal::checked_sub, |l, r| l - r)?,
+                env.clone(),
+            )),
+            Operands::Times(l_val, r_val) => {
+                Ok((eval_time(TimeOp::Sub, l_val, r_val, l, r)?, env.clone()))
+            }
+            Operands::Null => Ok((Null, env.clone())),
+            Operands::Tables(l, r) => {
+                if l.vars() != r.vars() {
+                    return Err(column_mismatch("difference", l, r));
+                }
+                let mut counts = HashMap::<Vec<Value>, usize>::new();
+                for row in r.rows() {
+                    *counts.entry(row.iter().map(Value).collect()).or_default() += 1;
+                }
+                let rows = l
+                    .rows()
+                    .iter()
+                    .filter(|row| {
+                        match counts.get_mut(&row.iter().map(Value).collect::<Vec<_>>()) {
+                            Some(count) if *count > 0 => {
+                                // A bag loses one copy of a row for each copy removed.
+                                if sem == Semantics::Bag {
+                                    *count -= 1;
+                                }
+                                false
+                            }
+                            _ => true,
+                        }
+                    })
+                    .cloned()
+                    .collect();
+                let columns = l.columns().to_vec();
+                let table = Relation::new(l.vars().to_vec(), rows)?.declare(columns)?;
+                Ok((Table(table), env.clone()))
+            }
+        },
+        Product(l_exp, r_exp) => match eval_operands(l_exp, r_exp, env, sem)? {
+            Operands::Numbers(n) => Ok((
+                n.apply(i64::checked_mul, Decimal::checked_mul, |l, r| l * r)?,
+                env.clone(),
+            )),
+            Operands::Times(l_val, r_val) => Ok((
+                eval_time(TimeOp::Mul, l_val, r_val, l_exp, r_exp)?,
+                env.clone(),
+            )),
+            Operands::Null => Ok((Null, env.clone())),
+            Operands::Tables(l, r) => {
+                let rows = l
+                    .rows()
+                    .iter()
+                    .flat_map(|l_row| {
+                        r.rows()
+                            .iter()
+                            .map(move |r_row| [l_row.as_slice(), r_row].concat())
+                    })
+                    .collect();
+                let vars = qualify(l_exp, l.vars(), r_exp, r.vars()).0;
+                let rows = distinct(rows, sem);
+                Ok((Table(Relation::new(vars, rows)?), env.clone()))
+            }
+        },
+        Divide(l, r) => {
+            let (l, r) = (eval_number(l, env, sem)?, eval_number(r, env, sem)?);
+            let Some(numbers) = Numbers::new(&l, &r) else {
+                return Ok((Null, env.clone()));
             };
-            let exps = l_exps
-                .chunks(max(l_vars.len(), 1))
-                .flat_map(|l_row| {
-                    r_exps
-                        .chunks(max(r_vars.len(), 1))
-                        .flat_map(move |r_row| [l_row, r_row].concat())
-                })
+            Ok((numbers.divide()?, env.clone()))
+        }
+        Join(l, r) => {
+            let l = eval_table(l, env, sem)?;
+            let r = eval_table(r, env, sem)?;
+            let shared = l
+                .vars()
+                .iter()
+                .enumerate()
+                .filter_map(|(i, var)| r.index(var).ok().map(|j| (i, j)))
                 .collect::<Vec<_>>();
-            let vars = [l_vars, r_vars].concat();
-            Ok((Table(vars, exps), env.clone()))
+            let rest = (0..r.vars().len())
+                .filter(|&j| shared.iter().all(|&(_, k)| k != j))
+                .collect::<Vec<_>>();
+            let mut rows = vec![];
+            for l_row in l.rows() {
+                for r_row in r.rows() {
+                    // Like `==`, null doesn't match anything, even null
+                    if shared
+                        .iter()
+                        .all(|&(i, j)| Value(&l_row[i]) == Value(&r_row[j]) && l_row[i] != Null)
+                    {
+                        let rest = rest.iter().map(|&j| r_row[j].clone());
+                        rows.push(l_row.iter().cloned().chain(rest).collect());
+                    }
+                }
+            }
+            let vars = l
+                .vars()
+                .iter()
+                .cloned()
+                .chain(rest.iter().map(|&j| r.vars()[j].clone()))
+                .collect();
+            Ok((Table(Relation::new(vars, rows)?), env.clone()))
+        }
+        JoinOn(l_exp, r_exp, cond) => {
+            let l = eval_table(l_exp, env, sem)?;
+            let r = eval_table(r_exp, env, sem)?;
+            let (vars, names) = qualify(l_exp, l.vars(), r_exp, r.vars());
+            let outer = outer_env([cond.as_ref()], env);
+            let mut rows = vec![];
+            for l_row in l.rows() {
+                for r_row in r.rows() {
+                    let row = [l_row.as_slice(), r_row].concat();
+                    let env = row_env(
+                        &outer,
+                        names
+                            .iter()
+                            .zip(&row)
+                            .flat_map(|(names, exp)| names.iter().map(move |name| (name, exp))),
+                    );
+                    if test(cond, &env, sem)? {
+                        rows.push(row);
+                    }
+                }
+            }
+            Ok((Table(Relation::new(vars, rows)?), env.clone()))
         }
-        Table(l, r) => {
-            let exps = r
+        Table(table) => {
+            let rows = table
+                .rows()
                 .iter()
-                .map(|exp| eval(exp, env).map(|(exp, _)| exp))
-                .collect::<Result<Vec<Exp>, String>>()?;
-            Ok((Table(l.clone(), exps), env.clone()))
+                .map(|row| {
+                    row.iter()
+                        .map(|exp| eval_with(exp, env, sem).map(|(exp, _)| exp))
+                        .collect()
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let rows = distinct(rows, sem);
+            let columns = table.columns().to_vec();
+            let table = Relation::new(table.vars().to_vec(), rows)?.declare(columns)?;
+            Ok((Table(table), env.clone()))
         }
+        // `||` and `&&` are null only when the other side doesn't decide them
         Or(l, r) => {
-            if let (Bool(true), _) = eval(l, env)? {
+            let (l, _) = eval_with(l, env, sem)?;
+            if l == Bool(true) {
                 return Ok((Bool(true), env.clone()));
             }
-            if let (Bool(true), _) = eval(r, env)? {
-                return Ok((Bool(true), env.clone()));
+            match eval_with(r, env, sem)? {
+                (Bool(true), _) => Ok((Bool(true), env.clone())),
+                (Null, _) => Ok((Null, env.clone())),
+                _ if l == Null => Ok((Null, env.clone())),
+                _ => Ok((Bool(false), env.clone())),
             }
-            Ok((Bool(false), env.clone()))
         }
         Equals(l, r) => {
-            let (l, _) = eval(l, env)?;
-            let (r, _) = eval(r, env)?;
-            Ok((Bool(l == r), env.clone()))
+            let (l, _) = eval_with(l, env, sem)?;
+            let (r, _) = eval_with(r, env, sem)?;
+            if l == Null || r == Null {
+                return Ok((Null, env.clone()));
+            }
+            // Numbers of different types are equal when their values are
+            match Numbers::new(&l, &r) {
+                Some(numbers) => Ok((Bool(numbers.cmp().is_eq()), env.clone())),
+                None => Ok((Bool(l == r), env.clone())),
+            }
         }
         And(l, r) => {
-            if let (Bool(false), _) = eval(l, env)? {
+            let (l, _) = eval_with(l, env, sem)?;
+            if l == Bool(false) {
                 return Ok((Bool(false), env.clone()));
             }
-            if let (Bool(false), _) = eval(r, env)? {
-                return Ok((Bool(false), env.clone()));
+            match eval_with(r, env, sem)? {
+                (Bool(false), _) => Ok((Bool(false), env.clone())),
+                (Null, _) => Ok((Null, env.clone())),
+                _ if l == Null => Ok((Null, env.clone())),
+                _ => Ok((Bool(true), env.clone())),
             }
-            Ok((Bool(true), env.clone()))
         }
-        Not(exp) => {
-            let exp = eval(exp, env)?;
-            match exp {
-                (Bool(bool), _) => Ok((Bool(!bool), env.clone())),
-                _ => Err(format!("Expected boolean, found {:?}", exp)),
+        Less(l, r) => Ok((eval_compare(l, r, env, Ordering::is_lt, sem)?, env.clone())),
+        LessEquals(l, r) => Ok((eval_compare(l, r, env, Ordering::is_le, sem)?, env.clone())),
+        Greater(l, r) => Ok((eval_compare(l, r, env, Ordering::is_gt, sem)?, env.clone())),
+        GreaterEquals(l, r) => Ok((eval_compare(l, r, env, Ordering::is_ge, sem)?, env.clone())),
+        Not(exp) => match eval_with(exp, env, sem)? {
+            (Bool(bool), _) => Ok((Bool(!bool), env.clone())),
+            (Null, _) => Ok((Null, env.clone())),
+            (found, _) => Err(Error::type_mismatch("boolean", &found).at_exp(exp)),
+        },
+        IsNull(exp) => {
+            let (exp, _) = eval_with(exp, env, sem)?;
+            Ok((Bool(exp == Null), env.clone()))
+        }
+        Like(l, r) => match (eval_str(l, env, sem)?, eval_str(r, env, sem)?) {
+            (Some(text), Some(pattern)) => Ok((Bool(like(&text, &pattern)), env.clone())),
+            _ => Ok((Null, env.clone())),
+        },
+        Call(f, args) => Ok((eval_call(*f, args, env, sem)?, env.clone())),
+        Schema(table) => {
+            let table = eval_table(table, env, sem)?;
+            let rows = table
+                .vars()
+                .iter()
+                .zip(table.columns())
+                .map(|(var, column)| {
+                    vec![
+                        Str(var.clone()),
+                        Str(column.ty.name().to_string()),
+                        Bool(column.key == Some(Key::Primary)),
+                        Bool(column.key == Some(Key::Unique)),
+                        column
+                            .references
+                            .as_ref()
+                            .map_or(Null, |reference| Str(reference.to_string())),
+                    ]
+                })
+                .collect();
+            let vars = ["column", "type", "key", "unique", "references"].map(String::from);
+            let types = [Type::Str, Type::Str, Type::Bool, Type::Bool, Type::Str];
+            let table =
+                Relation::new(vars.to_vec(), rows)?.declare(types.map(Column::typed).to_vec())?;
+            Ok((Table(table), env.clone()))
+        }
+        Closure(closure) => {
+            // Capture the values of the variables and versions the body
+            // uses. The body can outlive its source, so its spans go.
+            let mut closure = closure.clone();
+            closure.body = Box::new(closure.body.without_spans());
+            let captured = closure
+                .captures()
+                .into_iter()
+                .filter_map(|var| env.get(&var).map(|exp| (var, exp.clone())));
+            closure.env.extend(captured);
+            closure.env.sort_by(|(l, _), (r, _)| l.cmp(r));
+            Ok((Closure(closure), env.clone()))
+        }
+        Apply(f, args) => {
+            let closure = match f.unspanned() {
+                Var(var) if !env.contains_key(var) => {
+                    return Err(Error::UnknownFunction(var.clone()))
+                }
+                _ => match eval_with(f, env, sem)? {
+                    (Closure(closure), _) => closure,
+                    (found, _) => return Err(Error::type_mismatch("function", &found).at_exp(f)),
+                },
+            };
+            if args.len() != closure.params.len() {
+                return Err(Error::Arity {
+                    function: closure.name,
+                    expected: closure.params.len(),
+                    found: args.len(),
+                });
+            }
+            let mut body_env = closure.env.into_iter().collect::<Env>();
+            for (param, arg) in closure.params.into_iter().zip(args) {
+                let (value, _) = eval_with(arg, env, sem)?;
+                body_env.insert(param, value);
             }
+            let (result, _) = eval_with(&closure.body, &body_env, sem)?;
+            Ok((result, env.clone()))
         }
         Var(var) => match env.get(var) {
             Some(exp) => Ok((exp.clone(), env.clone())),
-            None => Err(format!("Variable `{}` not defined", var)),
+            None => Err(Error::UnknownVariable(var.clone())),
         },
+        Exp::Version(var, version) => {
+            let var = version.name(var);
+            match env.get(&var) {
+                Some(exp) => Ok((exp.clone(), env.clone())),
+                None => Err(Error::UnknownVariable(var)),
+            }
+        }
+        Spanned(span, exp) => eval_with(exp, env, sem).map_err(|e| e.at(*span)),
         exp => Ok((exp.clone(), env.clone())),
     }
 }
+
+/// Remove repeated rows, keeping the first of each, unless tables are bags.
+fn distinct(rows: Vec<Vec<Exp>>, sem: Semantics) -> Vec<Vec<Exp>> {
+    if sem == Semantics::Bag {
+        return rows;
+    }
+    let mut seen = HashSet::new();
+    let first = rows
+        .iter()
+        .map(|row| seen.insert(row.iter().map(Value).collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+    rows.into_iter()
+        .zip(first)
+        .filter_map(|(row, first)| first.then_some(row))
+        .collect()
+}
+
+/// The variables around row expressions that they use, which they can see
+/// alongside the row's columns.
+fn outer_env<'a>(exps: impl IntoIterator<Item = &'a Exp>, env: &Env) -> Env {
+    exps.into_iter()
+        .flat_map(|exp| analyse_reads(exp, &HashSet::new()))
+        .filter_map(|var| env.get(&var).map(|exp| (var, exp.clone())))
+        .collect()
+}
+
+/// The environment of a row expression, where columns hide variables of
+/// the same name.
+fn row_env<'a>(outer: &Env, columns: impl IntoIterator<Item = (&'a String, &'a Exp)>) -> Env {
+    let mut env = outer.clone();
+    env.extend(
+        columns
+            .into_iter()
+            .map(|(var, exp)| (var.clone(), exp.clone())),
+    );
+    env
+}
+
+/// Evaluate a row condition. A null condition doesn't hold.
+fn test(cond: &Exp, env: &Env, sem: Semantics) -> Result<bool, Error> {
+    match eval_with(cond, env, sem)? {
+        (Bool(bool), _) => Ok(bool),
+        (Null, _) => Ok(false),
+        (exp, _) => Err(Error::type_mismatch("boolean", &exp).at_exp(cond)),
+    }
+}
+
+/// The columns of two tables side by side. Names both tables use are
+/// qualified by the table they come from, like `Staff.id`, or by `left` and
+/// `right` when the tables aren't named variables. Alongside each column are
+/// the names a condition can refer to it by.
+pub(crate) fn qualify(
+    l: &Exp,
+    l_vars: &[String],
+    r: &Exp,
+    r_vars: &[String],
+) -> (Vec<String>, Vec<Vec<String>>) {
+    let (l_name, r_name) = match (table_name(l), table_name(r)) {
+        (Some(l), Some(r)) if l != r => (l, r),
+        _ => ("left".to_string(), "right".to_string()),
+    };
+    let side = |name: &str, vars: &[String], others: &[String]| {
+        vars.iter()
+            .map(|var| {
+                let qualified = format!("{}.{}", name, var);
+                if others.contains(var) {
+                    (qualified.clone(), vec![qualified])
+                } else {
+                    (var.clone(), vec![var.clone(), qualified])
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    side(&l_name, l_vars, r_vars)
+        .into_iter()
+        .chain(side(&r_name, r_vars, l_vars))
+        .unzip()
+}
+
+fn table_name(exp: &Exp) -> Option<String> {
+    match exp {
+        Var(var) | Exp::Version(var, _) => Some(var.clone()),
+        Spanned(_, exp) => table_name(exp),
+        _ => None,
+    }
+}
+
+fn eval_table(exp: &Exp, env: &Env, sem: Semantics) -> Result<Relation, Error> {
+    match eval_with(exp, env, sem)? {
+        (Table(table), _) => Ok(table),
+        (found, _) => Err(Error::type_mismatch("table", &found).at_exp(exp)),
+    }
+}
+
+/// Compute an aggregate over the rows of one group, ignoring null values.
+/// `avg` of integers rounds toward zero.
+fn aggregate(agg: &Agg, column: Option<usize>, rows: &[&[Exp]]) -> Result<Exp, Error> {
+    let values = column
+        .map(|i| {
+            rows.iter()
+                .map(|row| &row[i])
+                .filter(|exp| **exp != Null)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    // Like SQL, a sum over nothing is 0, but there is no average, least or
+    // greatest value of nothing
+    match agg {
+        Agg::Count => Ok(Int(rows.len() as i64)),
+        Agg::Sum(_) => sum(&values),
+        Agg::Avg(_) if values.is_empty() => Ok(Null),
+        Agg::Avg(_) => Numbers::new(&sum(&values)?, &Int(values.len() as i64))
+            .expect("a sum is a number")
+            .divide(),
+        Agg::Min(_) => Ok(extreme(&values, Ordering::Less)?.unwrap_or(Null)),
+        Agg::Max(_) => Ok(extreme(&values, Ordering::Greater)?.unwrap_or(Null)),
+    }
+}
+
+fn sum(values: &[&Exp]) -> Result<Exp, Error> {
+    values
+        .iter()
+        .try_fold(Int(0), |acc, value| match Numbers::new(&acc, value) {
+            Some(numbers) => numbers.apply(i64::checked_add, Decimal::checked_add, |l, r| l + r),
+            None => Err(Error::type_mismatch("number", value)),
+        })
+}
+
+/// The least or greatest of some values, which must all have the same type.
+fn extreme(values: &[&Exp], ordering: Ordering) -> Result<Option<Exp>, Error> {
+    let mut best: Option<&Exp> = None;
+    for &value in values {
+        match best {
+            None if compare(value, value).is_none() => {
+                return Err(Error::type_mismatch("number, string or boolean", value))
+            }
+            None => best = Some(value),
+            Some(exp) => match compare(value, exp) {
+                Some(order) if order == ordering => best = Some(value),
+                Some(_) => {}
+                None => return Err(Error::type_mismatch(exp.describe(), value)),
+            },
+        }
+    }
+    Ok(best.cloned())
+}
+
+fn compare(l: &Exp, r: &Exp) -> Option<Ordering> {
+    match (l, r) {
+        (Str(l), Str(r)) => Some(l.cmp(r)),
+        (Bool(l), Bool(r)) => Some(l.cmp(r)),
+        (Date(l), Date(r)) => Some(l.cmp(r)),
+        (Time(l), Time(r)) => Some(l.cmp(r)),
+        (Timestamp(l), Timestamp(r)) => Some(l.cmp(r)),
+        (Interval(l), Interval(r)) => Some(l.cmp(r)),
+        (l, r) => Numbers::new(l, r).map(Numbers::cmp),
+    }
+}
+
+/// A value that equals the values `==` finds equal, so that numbers of
+/// different types are the same when their values are. Unlike `==`, null
+/// equals null. Rows are told apart, grouped and matched by their values.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Value<'a>(pub &'a Exp);
+
+impl PartialEq for Value<'_> {
+    fn eq(&self, other: &Value) -> bool {
+        match Numbers::new(self.0, other.0) {
+            Some(numbers) => numbers.cmp().is_eq(),
+            None => self.0 == other.0,
+        }
+    }
+}
+
+impl Eq for Value<'_> {}
+
+impl Hash for Value<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal numbers are equal as floats too
+        match to_float(self.0) {
+            Some(float) => F64(float).hash(state),
+            None => self.0.hash(state),
+        }
+    }
+}
+
+/// Two numbers, converted to the wider of their types: integers widen to
+/// decimals, and both widen to floats.
+enum Numbers {
+    Ints(i64, i64),
+    Decimals(Decimal, Decimal),
+    Floats(f64, f64),
+}
+
+impl Numbers {
+    fn new(l: &Exp, r: &Exp) -> Option<Numbers> {
+        match (l, r) {
+            (Int(l), Int(r)) => Some(Numbers::Ints(*l, *r)),
+            (Int(_) | Decimal(_), Int(_) | Decimal(_)) => {
+                Some(Numbers::Decimals(to_decimal(l)?, to_decimal(r)?))
+            }
+            (l, r) => Some(Numbers::Floats(to_float(l)?, to_float(r)?)),
+        }
+    }
+
+    fn apply(
+        self,
+        int: fn(i64, i64) -> Option<i64>,
+        decimal: fn(Decimal, Decimal) -> Option<Decimal>,
+        float: fn(f64, f64) -> f64,
+    ) -> Result<Exp, Error> {
+        match self {
+            Numbers::Ints(l, r) => checked(int(l, r)),
+            Numbers::Decimals(l, r) => decimal(l, r)
+                .map(Decimal)
+                .ok_or_else(|| Error::Arithmetic("decimal overflow".to_string())),
+            Numbers::Floats(l, r) => match float(l, r) {
+                float if float.is_finite() => Ok(Float(F64(float))),
+                _ => Err(Error::Arithmetic("float overflow".to_string())),
+            },
+        }
+    }
+
+    /// Divide the numbers. Integer division rounds toward zero.
+    fn divide(self) -> Result<Exp, Error> {
+        let zero = match self {
+            Numbers::Ints(_, r) => r == 0,
+            Numbers::Decimals(_, r) => r.is_zero(),
+            Numbers::Floats(_, r) => r == 0.0,
+        };
+        if zero {
+            return Err(Error::Arithmetic("division by zero".to_string()));
+        }
+        self.apply(i64::checked_div, Decimal::checked_div, |l, r| l / r)
+    }
+
+    fn cmp(self) -> Ordering {
+        match self {
+            Numbers::Ints(l, r) => l.cmp(&r),
+            Numbers::Decimals(l, r) => l.cmp(&r),
+            Numbers::Floats(l, r) => F64(l).cmp(&F64(r)),
+        }
+    }
+}
+
+fn to_decimal(exp: &Exp) -> Option<Decimal> {
+    match exp {
+        Int(int) => Some(Decimal::from(*int)),
+        Decimal(decimal) => Some(*decimal),
+        _ => None,
+    }
+}
+
+fn to_float(exp: &Exp) -> Option<f64> {
+    match exp {
+        Int(int) => Some(*int as f64),
+        Decimal(decimal) => decimal.to_f64(),
+        Float(float) => Some(float.0),
+        _ => None,
+    }
+}
+
+fn is_number(exp: &Exp) -> bool {
+    matches!(exp, Int(_) | Float(_) | Decimal(_))
+}
+
+fn is_time(exp: &Exp) -> bool {
+    matches!(exp, Date(_) | Time(_) | Timestamp(_) | Interval(_))
+}
+
+/// The operands of `+`, `-` or `*`, which work on tables, numbers, or dates
+/// and times. Arithmetic with a null number or time is null.
+enum Operands {
+    Tables(Relation, Relation),
+    Numbers(Numbers),
+    Times(Exp, Exp),
+    Null,
+}
+
+/// Do arithmetic where either operand is a date, time or interval.
+fn eval_time(op: TimeOp, l_val: Exp, r_val: Exp, l: &Exp, r: &Exp) -> Result<Exp, Error> {
+    time::result_type(op, &Type::of(&l_val), &Type::of(&r_val)).map_err(|m| match m {
+        Mismatch::Left => Error::type_mismatch("table or number", &l_val).at_exp(l),
+        Mismatch::Right(expected) => Error::type_mismatch(expected, &r_val).at_exp(r),
+    })?;
+    time::apply(op, &l_val, &r_val)
+}
+
+fn eval_operands(l: &Exp, r: &Exp, env: &Env, sem: Semantics) -> Result<Operands, Error> {
+    match (eval_with(l, env, sem)?, eval_with(r, env, sem)?) {
+        ((Table(l), _), (Table(r), _)) => Ok(Operands::Tables(l, r)),
+        ((Null, _), (r_exp, _)) if r_exp == Null || is_number(&r_exp) || is_time(&r_exp) => {
+            Ok(Operands::Null)
+        }
+        ((Table(..), _), (found, _)) => Err(Error::type_mismatch("table", &found).at_exp(r)),
+        ((l_exp, _), (Null, _)) if is_time(&l_exp) => Ok(Operands::Null),
+        ((l_exp, _), (r_exp, _)) if is_time(&l_exp) || is_time(&r_exp) => {
+            Ok(Operands::Times(l_exp, r_exp))
+        }
+        ((l_exp, _), (r_exp, _)) if is_number(&l_exp) => match Numbers::new(&l_exp, &r_exp) {
+            Some(numbers) => Ok(Operands::Numbers(numbers)),
+            None if r_exp == Null => Ok(Operands::Null),
+            None => Err(Error::type_mismatch("number", &r_exp).at_exp(r)),
+        },
+        ((found, _), _) => Err(Error::type_mismatch("table or number", &found).at_exp(l)),
+    }
+}
+
+/// Evaluate a number, or null.
+fn eval_number(exp: &Exp, env: &Env, sem: Semantics) -> Result<Exp, Error> {
+    match eval_with(exp, env, sem)? {
+        (exp, _) if exp == Null || is_number(&exp) => Ok(exp),
+        (found, _) => Err(Error::type_mismatch("number", &found).at_exp(exp)),
+    }
+}
+
+/// Evaluate a string, or null.
+fn eval_str(exp: &Exp, env: &Env, sem: Semantics) -> Result<Option<String>, Error> {
+    match eval_with(exp, env, sem)? {
+        (Str(str), _) => Ok(Some(str)),
+        (Null, _) => Ok(None),
+        (found, _) => Err(Error::type_mismatch("string", &found).at_exp(exp)),
+    }
+}
+
+/// Apply a builtin function. The string functions are null when any
+/// argument is.
+fn eval_call(f: Function, args: &[Exp], env: &Env, sem: Semantics) -> Result<Exp, Error> {
+    if args.len() != f.arity() {
+        return Err(Error::Arity {
+            function: f.name().to_string(),
+            expected: f.arity(),
+            found: args.len(),
+        });
+    }
+    match f {
+        Function::Not | Function::Schema => {
+            return eval_with(&f.apply(args.to_vec()), env, sem).map(|(exp, _)| exp)
+        }
+        Function::Now => return Ok(Timestamp(Utc::now())),
+        _ => {}
+    }
+    let mut strs = vec![];
+    for arg in args {
+        match eval_str(arg, env, sem)? {
+            Some(str) => strs.push(str),
+            None => return Ok(Null),
+        }
+    }
+    Ok(match (f, strs.as_slice()) {
+        (Function::Length, [str]) => Int(str.chars().count() as i64),
+        (Function::Lower, [str]) => Str(str.to_lowercase()),
+        (Function::Upper, [str]) => Str(str.to_uppercase()),
+        (Function::Concat, [l, r]) => Str(format!("{}{}", l, r)),
+        (Function::Contains, [str, part]) => Bool(str.contains(part.as_str())),
+        (Function::StartsWith, [str, prefix]) => Bool(str.starts_with(prefix.as_str())),
+        (Function::Date | Function::Time | Function::Timestamp | Function::Interval, [text]) => {
+            time::convert(f, text).expect("a conversion")?
+        }
+        _ => unreachable!("`{}` is applied above", f.name()),
+    })
+}
+
+/// Whether text matches a `like` pattern, where `%` matches any run of
+/// characters, `_` matches any one character, and `\` makes the next
+/// character match only itself.
+fn like(text: &str, pattern: &str) -> bool {
+    enum Token {
+        Any,
+        One,
+        Char(char),
+    }
+    let mut tokens = vec![];
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '%' => Token::Any,
+            '_' => Token::One,
+            '\\' => Token::Char(chars.next().unwrap_or('\\')),
+            c => Token::Char(c),
+        });
+    }
+
+    // Match greedily, going back to the last `%` to take one more character
+    // when the rest doesn't match
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match tokens.get(p) {
+            Some(Token::Any) => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(Token::One) => (t, p) = (t + 1, p + 1),
+            Some(Token::Char(c)) if *c == text[t] => (t, p) = (t + 1, p + 1),
+            _ => match backtrack {
+                Some((any, start)) => {
+                    backtrack = Some((any, start + 1));
+                    (t, p) = (start + 1, any + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[p..].iter().all(|token| matches!(token, Token::Any))
+}
+
+fn eval_compare(
+    l: &Exp,
+    r: &Exp,
+    env: &Env,
+    test: fn(Ordering) -> bool,
+    sem: Semantics,
+) -> Result<Exp, Error> {
+    let (l_exp, _) = eval_with(l, env, sem)?;
+    let (r_exp, _) = eval_with(r, env, sem)?;
+    if l_exp == Null || r_exp == Null {
+        return Ok(Null);
+    }
+    match compare(&l_exp, &r_exp) {
+        Some(ordering) => Ok(Bool(test(ordering))),
+        None if compare(&l_exp, &l_exp).is_none() => {
+            Err(Error::type_mismatch("number, string or boolean", &l_exp).at_exp(l))
+        }
+        None => Err(Error::type_mismatch(l_exp.describe(), &r_exp).at_exp(r)),
+    }
+}
+
+fn checked(result: Option<i64>) -> Result<Exp, Error> {
+    result
+        .map(Int)
+        .ok_or_else(|| Error::Arithmetic("integer overflow".to_string()))
+}
+
+fn column_mismatch(op: &str, left: Relation, right: Relation) -> Error {
+    Error::ColumnMismatch {
+        op: op.to_string(),
+        left: left.into_parts().0,
+        right: right.into_parts().0,
+    }
+}
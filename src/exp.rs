@@ -1,18 +1,517 @@
-#[derive(Debug, PartialEq, Clone)]
+use crate::{check::schema_type, Relation, Span, Type};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Exp {
     Let(String, Box<Exp>, Box<Exp>),
     Select(Vec<String>, Box<Exp>),
+    Rename(Vec<(String, String)>, Box<Exp>),
+    Extend(Vec<(String, Exp)>, Box<Exp>),
+    Group(Vec<String>, Vec<Agg>, Box<Exp>),
     Where(Box<Exp>, Box<Exp>),
     Union(Box<Exp>, Box<Exp>),
     Difference(Box<Exp>, Box<Exp>),
     Product(Box<Exp>, Box<Exp>),
-    Table(Vec<String>, Vec<Exp>),
+    Divide(Box<Exp>, Box<Exp>),
+    Join(Box<Exp>, Box<Exp>),
+    JoinOn(Box<Exp>, Box<Exp>, Box<Exp>),
+    Table(Relation),
     Or(Box<Exp>, Box<Exp>),
     Equals(Box<Exp>, Box<Exp>),
     And(Box<Exp>, Box<Exp>),
+    Less(Box<Exp>, Box<Exp>),
+    LessEquals(Box<Exp>, Box<Exp>),
+    Greater(Box<Exp>, Box<Exp>),
+    GreaterEquals(Box<Exp>, Box<Exp>),
     Not(Box<Exp>),
+    IsNull(Box<Exp>),
+    Like(Box<Exp>, Box<Exp>),
+    Call(Function, Vec<Exp>),
+    /// A user-defined function applied to its arguments.
+    Apply(Box<Exp>, Vec<Exp>),
+    Schema(Box<Exp>),
+    Null,
     Bool(bool),
     Int(i64),
+    Float(F64),
+    Decimal(Decimal),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Timestamp(DateTime<Utc>),
+    Interval(TimeDelta),
     Str(String),
+    Closure(Box<Closure>),
     Var(String),
+    Version(String, Version),
+    Spanned(Span, Box<Exp>),
+}
+
+impl Exp {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Exp::Table(..) => "table",
+            Exp::Bool(_) => "boolean",
+            Exp::Int(_) => "integer",
+            Exp::Float(_) => "float",
+            Exp::Decimal(_) => "decimal",
+            Exp::Date(_) => "date",
+            Exp::Time(_) => "time",
+            Exp::Timestamp(_) => "timestamp",
+            Exp::Interval(_) => "interval",
+            Exp::Str(_) => "string",
+            Exp::Closure(_) => "function",
+            Exp::Null => "null",
+            Exp::Spanned(_, exp) => exp.describe(),
+            _ => "expression",
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self.unspanned() == Exp::Null
+    }
+
+    pub fn unspanned(&self) -> &Exp {
+        match self {
+            Exp::Spanned(_, exp) => exp.unspanned(),
+            exp => exp,
+        }
+    }
+
+    /// The expression with every span removed, for keeping beyond the source
+    /// the spans point into.
+    pub fn without_spans(self) -> Exp {
+        let strip = |exp: Box<Exp>| Box::new(exp.without_spans());
+        let strip_all = |exps: Vec<Exp>| exps.into_iter().map(Exp::without_spans).collect();
+        match self {
+            Exp::Let(var, exp, body) => Exp::Let(var, strip(exp), strip(body)),
+            Exp::Select(vars, r) => Exp::Select(vars, strip(r)),
+            Exp::Rename(renames, r) => Exp::Rename(renames, strip(r)),
+            Exp::Extend(columns, r) => Exp::Extend(
+                columns
+                    .into_iter()
+                    .map(|(var, exp)| (var, exp.without_spans()))
+                    .collect(),
+                strip(r),
+            ),
+            Exp::Group(keys, aggs, r) => Exp::Group(keys, aggs, strip(r)),
+            Exp::Where(l, r) => Exp::Where(strip(l), strip(r)),
+            Exp::Union(l, r) => Exp::Union(strip(l), strip(r)),
+            Exp::Difference(l, r) => Exp::Difference(strip(l), strip(r)),
+            Exp::Product(l, r) => Exp::Product(strip(l), strip(r)),
+            Exp::Divide(l, r) => Exp::Divide(strip(l), strip(r)),
+            Exp::Join(l, r) => Exp::Join(strip(l), strip(r)),
+            Exp::JoinOn(l, r, cond) => Exp::JoinOn(strip(l), strip(r), strip(cond)),
+            Exp::Table(table) => Exp::Table(table.map_cells(Exp::without_spans)),
+            Exp::Or(l, r) => Exp::Or(strip(l), strip(r)),
+            Exp::Equals(l, r) => Exp::Equals(strip(l), strip(r)),
+            Exp::And(l, r) => Exp::And(strip(l), strip(r)),
+            Exp::Less(l, r) => Exp::Less(strip(l), strip(r)),
+            Exp::LessEquals(l, r) => Exp::LessEquals(strip(l), strip(r)),
+            Exp::Greater(l, r) => Exp::Greater(strip(l), strip(r)),
+            Exp::GreaterEquals(l, r) => Exp::GreaterEquals(strip(l), strip(r)),
+            Exp::Not(exp) => Exp::Not(strip(exp)),
+            Exp::IsNull(exp) => Exp::IsNull(strip(exp)),
+            Exp::Like(l, r) => Exp::Like(strip(l), strip(r)),
+            Exp::Call(f, args) => Exp::Call(f, strip_all(args)),
+            Exp::Apply(f, args) => Exp::Apply(strip(f), strip_all(args)),
+            Exp::Schema(exp) => Exp::Schema(strip(exp)),
+            Exp::Closure(closure) => Exp::Closure(Box::new(Closure {
+                body: strip(closure.body),
+                env: closure
+                    .env
+                    .into_iter()
+                    .map(|(var, exp)| (var, exp.without_spans()))
+                    .collect(),
+                ..*closure
+            })),
+            Exp::Spanned(_, exp) => exp.without_spans(),
+            exp => exp,
+        }
+    }
+}
+
+/// A function defined like `active d = Staff ? dept == d`, with the values
+/// of the variables its body uses from where it was defined. Evaluating the
+/// definition captures them, so later definitions don't change it.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Closure {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Box<Exp>,
+    pub env: Vec<(String, Exp)>,
+}
+
+impl Closure {
+    /// The parameters and captured variables.
+    fn bound(&self) -> HashSet<String> {
+        let captured = self.env.iter().map(|(var, _)| var.clone());
+        self.params.iter().cloned().chain(captured).collect()
+    }
+
+    /// The variables the body reads that it hasn't captured.
+    pub fn uses(&self) -> HashSet<String> {
+        analyse_reads(&self.body, &self.bound())
+    }
+
+    /// The old versions of variables the body reads.
+    pub fn versions(&self) -> HashSet<(String, Version)> {
+        analyse_versions(&self.body)
+    }
+
+    /// What the function takes from where it's defined: the variables it
+    /// uses, and the old versions it reads by their names in an [`Env`].
+    ///
+    /// [`Env`]: crate::Env
+    pub fn captures(&self) -> HashSet<String> {
+        let bound = self.bound();
+        let versions = self
+            .versions()
+            .into_iter()
+            .map(|(var, version)| version.name(&var))
+            .filter(|name| !bound.contains(name));
+        self.uses().into_iter().chain(versions).collect()
+    }
+}
+
+/// A float with a total order, so that floats can be grouped, deduplicated
+/// and used in keys like any other value. As with `==`, `-0.0` equals `0.0`,
+/// and otherwise every value equals only itself. Evaluation never makes
+/// infinities or NaN.
+#[derive(Debug, Copy, Clone)]
+pub struct F64(pub f64);
+
+impl PartialEq for F64 {
+    fn eq(&self, other: &F64) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for F64 {}
+
+impl PartialOrd for F64 {
+    fn partial_cmp(&self, other: &F64) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for F64 {
+    fn cmp(&self, other: &F64) -> Ordering {
+        if self.0 == 0.0 && other.0 == 0.0 {
+            Ordering::Equal
+        } else {
+            self.0.total_cmp(&other.0)
+        }
+    }
+}
+
+impl Hash for F64 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Adding 0.0 turns -0.0 into 0.0
+        (self.0 + 0.0).to_bits().hash(state)
+    }
+}
+
+/// An aggregate computed over each group of rows by [`Exp::Group`].
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Agg {
+    Count,
+    Sum(String),
+    Min(String),
+    Max(String),
+    Avg(String),
+}
+
+impl Agg {
+    /// The function name, as written in source.
+    pub fn function(&self) -> &'static str {
+        match self {
+            Agg::Count => "count",
+            Agg::Sum(_) => "sum",
+            Agg::Min(_) => "min",
+            Agg::Max(_) => "max",
+            Agg::Avg(_) => "avg",
+        }
+    }
+
+    /// The column the aggregate reads, if any.
+    pub fn column(&self) -> Option<&str> {
+        match self {
+            Agg::Count => None,
+            Agg::Sum(var) | Agg::Min(var) | Agg::Max(var) | Agg::Avg(var) => Some(var),
+        }
+    }
+
+    /// The name of the result column, like `count` or `sum_salary`.
+    pub fn name(&self) -> String {
+        match self.column() {
+            Some(var) => format!("{}_{}", self.function(), var),
+            None => self.function().to_string(),
+        }
+    }
+}
+
+/// A function built into the language, applied to its arguments like
+/// `concat first last`. Functions are looked up by name in [`Function::ALL`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Function {
+    Not,
+    Schema,
+    Length,
+    Lower,
+    Upper,
+    Concat,
+    Contains,
+    StartsWith,
+    Date,
+    Time,
+    Timestamp,
+    Interval,
+    Now,
+}
+
+impl Function {
+    pub const ALL: [Function; 13] = [
+        Function::Not,
+        Function::Schema,
+        Function::Length,
+        Function::Lower,
+        Function::Upper,
+        Function::Concat,
+        Function::Contains,
+        Function::StartsWith,
+        Function::Date,
+        Function::Time,
+        Function::Timestamp,
+        Function::Interval,
+        Function::Now,
+    ];
+
+    pub fn lookup(name: &str) -> Option<Function> {
+        Function::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    /// The function name, as written in source.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Not => "not",
+            Function::Schema => "schema",
+            Function::Length => "length",
+            Function::Lower => "lower",
+            Function::Upper => "upper",
+            Function::Concat => "concat",
+            Function::Contains => "contains",
+            Function::StartsWith => "starts_with",
+            Function::Date => "date",
+            Function::Time => "time",
+            Function::Timestamp => "timestamp",
+            Function::Interval => "interval",
+            Function::Now => "now",
+        }
+    }
+
+    /// The types of the arguments, and of the result.
+    pub fn signature(&self) -> (Vec<Type>, Type) {
+        match self {
+            Function::Not => (vec![Type::Bool], Type::Bool),
+            Function::Schema => (vec![Type::Table(vec![])], schema_type()),
+            Function::Length => (vec![Type::Str], Type::Int),
+            Function::Lower | Function::Upper => (vec![Type::Str], Type::Str),
+            Function::Concat => (vec![Type::Str, Type::Str], Type::Str),
+            Function::Contains | Function::StartsWith => (vec![Type::Str, Type::Str], Type::Bool),
+            Function::Date => (vec![Type::Str], Type::Date),
+            Function::Time => (vec![Type::Str], Type::Time),
+            Function::Timestamp => (vec![Type::Str], Type::Timestamp),
+            Function::Interval => (vec![Type::Str], Type::Interval),
+            Function::Now => (vec![], Type::Timestamp),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.signature().0.len()
+    }
+
+    /// The expression that applies the function to arguments, which must
+    /// number [`Function::arity`].
+    pub fn apply(self, mut args: Vec<Exp>) -> Exp {
+        match self {
+            Function::Not => Exp::Not(Box::new(args.remove(0))),
+            Function::Schema => Exp::Schema(Box::new(args.remove(0))),
+            f => Exp::Call(f, args),
+        }
+    }
+}
+
+/// A reference to an earlier value of a persisted variable: either its version
+/// number, or a time at which to look it up.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Version {
+    Number(u64),
+    Time(String),
+}
+
+impl Version {
+    /// The instant a [`Version::Time`] refers to. A bare date means midnight
+    /// UTC at the start of that day.
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Version::Number(_) => None,
+            Version::Time(text) => parse_time(text),
+        }
+    }
+
+    /// The name `var` at this version has in an environment, like `Staff@1`.
+    pub fn name(&self, var: &str) -> String {
+        format!("{}@{}", var, self)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Number(n) => write!(f, "{}", n),
+            Version::Time(text) => write!(f, "'{}'", text),
+        }
+    }
+}
+
+pub fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|time| time.and_utc())
+        })
+}
+
+/// The variables an expression reads that aren't among those `defined`
+/// around it. Columns referred to in row expressions are included too, since
+/// which names are columns isn't known until the tables are.
+pub(crate) fn analyse_reads(exp: &Exp, defined: &HashSet<String>) -> HashSet<String> {
+    match exp {
+        Exp::Let(var, exp, body) => union(
+            analyse_reads(exp, defined),
+            analyse_reads(body, &union(single(var), defined.clone())),
+        ),
+        Exp::Select(_, r) => analyse_reads(r, defined),
+        Exp::Rename(_, r) => analyse_reads(r, defined),
+        Exp::Extend(columns, r) => columns
+            .iter()
+            .map(|(_, exp)| analyse_reads(exp, defined))
+            .fold(analyse_reads(r, defined), union),
+        Exp::Group(_, _, r) => analyse_reads(r, defined),
+        Exp::Where(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Union(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Difference(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Product(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Divide(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Join(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::JoinOn(l, r, cond) => union(
+            union(analyse_reads(l, defined), analyse_reads(r, defined)),
+            analyse_reads(cond, defined),
+        ),
+        // Declaring a reference reads the variable it refers to
+        Exp::Table(table) => table
+            .cells()
+            .flat_map(|exp| analyse_reads(exp, defined))
+            .chain(
+                table
+                    .references()
+                    .map(|(_, reference)| reference.var.clone())
+                    .filter(|var| !defined.contains(var)),
+            )
+            .collect(),
+        Exp::Or(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Equals(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::And(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Less(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::LessEquals(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Greater(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::GreaterEquals(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Not(exp) => analyse_reads(exp, defined),
+        Exp::IsNull(exp) => analyse_reads(exp, defined),
+        Exp::Like(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
+        Exp::Call(_, args) => args
+            .iter()
+            .map(|exp| analyse_reads(exp, defined))
+            .fold(empty(), union),
+        Exp::Apply(f, args) => args
+            .iter()
+            .map(|exp| analyse_reads(exp, defined))
+            .fold(analyse_reads(f, defined), union),
+        Exp::Closure(closure) => {
+            analyse_reads(&closure.body, &union(closure.bound(), defined.clone()))
+        }
+        Exp::Schema(exp) => analyse_reads(exp, defined),
+        Exp::Var(var) if !defined.contains(var) => single(var),
+        Exp::Spanned(_, exp) => analyse_reads(exp, defined),
+        _ => empty(),
+    }
+}
+
+/// The old versions of variables an expression reads, like `Staff@1`.
+pub(crate) fn analyse_versions(exp: &Exp) -> HashSet<(String, Version)> {
+    match exp {
+        Exp::Let(_, exp, body) => union(analyse_versions(exp), analyse_versions(body)),
+        Exp::Select(_, r) => analyse_versions(r),
+        Exp::Rename(_, r) => analyse_versions(r),
+        Exp::Extend(columns, r) => columns
+            .iter()
+            .map(|(_, exp)| analyse_versions(exp))
+            .fold(analyse_versions(r), union),
+        Exp::Group(_, _, r) => analyse_versions(r),
+        Exp::Where(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Union(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Difference(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Product(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Divide(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Join(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::JoinOn(l, r, cond) => union(
+            union(analyse_versions(l), analyse_versions(r)),
+            analyse_versions(cond),
+        ),
+        Exp::Table(table) => table.cells().flat_map(analyse_versions).collect(),
+        Exp::Or(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Equals(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::And(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Less(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::LessEquals(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Greater(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::GreaterEquals(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Not(exp) => analyse_versions(exp),
+        Exp::IsNull(exp) => analyse_versions(exp),
+        Exp::Like(l, r) => union(analyse_versions(l), analyse_versions(r)),
+        Exp::Call(_, args) => args.iter().map(analyse_versions).fold(empty(), union),
+        Exp::Apply(function, args) => args
+            .iter()
+            .map(analyse_versions)
+            .fold(analyse_versions(function), union),
+        Exp::Closure(closure) => analyse_versions(&closure.body),
+        Exp::Schema(exp) => analyse_versions(exp),
+        Exp::Version(var, version) => HashSet::from([(var.clone(), version.clone())]),
+        Exp::Spanned(_, exp) => analyse_versions(exp),
+        _ => empty(),
+    }
+}
+
+fn empty<T>() -> HashSet<T> {
+    HashSet::new()
+}
+
+fn single(s: &str) -> HashSet<String> {
+    HashSet::from([s.to_string()])
+}
+
+fn union<T: Eq + Hash + Clone>(a: HashSet<T>, b: HashSet<T>) -> HashSet<T> {
+    a.union(&b).cloned().collect()
 }
@@ -1,19 +1,36 @@
+mod check;
 mod cli;
 mod client;
+mod error;
 mod eval;
 mod exp;
 mod parse;
+mod relation;
+mod repl;
+mod response;
 mod serialise;
 mod server;
+mod store;
+mod time;
 
-pub use cli::{Cli, Client, Server};
+pub use check::{check, type_env, Type, TypeEnv};
+pub use cli::{Check, Cli, Client, Repl, Server};
 pub use client::client;
-pub use eval::{eval, Env};
-pub use exp::Exp;
-pub use parse::{parse, Bexp, Op, Side};
+pub use error::{Error, Span};
+pub use eval::{eval, eval_with, Env, Semantics};
+pub use exp::{Agg, Closure, Exp, Function, Version, F64};
+pub use parse::{parse, parse_spanned, Bexp, Op, Side};
+pub use relation::{Column, Key, Reference, Relation};
+pub use repl::{is_complete, repl, Session};
+pub use response::Response;
 pub use serialise::serialise;
-pub use server::server;
+pub use server::{execute, server};
+pub use store::{decode_name, encode_name, Locks, Store};
 
-pub fn read_eval(text: &str, env: &Env) -> Result<(Exp, Env), String> {
-    eval(&parse(text)?, env)
+pub fn read_eval(text: &str, env: &Env) -> Result<(Exp, Env), Error> {
+    eval(&parse_spanned(text)?, env)
+}
+
+pub fn read_eval_with(text: &str, env: &Env, sem: Semantics) -> Result<(Exp, Env), Error> {
+    eval_with(&parse_spanned(text)?, env, sem)
 }
@@ -1,38 +1,89 @@
-use sdb::{client, read_eval, serialise, server, Cli, Env};
+use sdb::{
+    check, client, parse_spanned, read_eval_with, repl, serialise, server, Cli, Env, Response,
+    Semantics, TypeEnv,
+};
 
 use clap::Parser;
-use std::fs;
+use std::{fs, process::ExitCode};
 
-fn main() {
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
     match cli {
         Cli::Run(conf) => {
-            let text = if conf.expression {
-                conf.target
-            } else {
-                match fs::read_to_string(conf.target) {
-                    Ok(text) => text,
-                    Err(e) => return eprintln!("Error reading file: {}", e),
-                }
+            let Some(text) = read_target(conf.target, conf.expression) else {
+                return ExitCode::FAILURE;
             };
 
+            let semantics = if conf.bag {
+                Semantics::Bag
+            } else {
+                Semantics::Set
+            };
             match conf.server {
                 Some(url) => match client(&text, &url) {
-                    Ok(result) => println!("{}", result),
-                    Err(e) => eprintln!("Error running client: {}", e),
+                    Ok(Response::Ok(result)) => println!("{}", result),
+                    Ok(Response::Err { message, .. }) => {
+                        eprintln!("{}", message);
+                        return ExitCode::FAILURE;
+                    }
+                    Err(e) => {
+                        eprintln!("Error running client: {}", e);
+                        return ExitCode::FAILURE;
+                    }
                 },
-                None => match read_eval(&text, &Env::new()) {
+                None => match read_eval_with(&text, &Env::new(), semantics) {
                     Ok((result, _)) => println!("{}", serialise(result)),
-                    Err(e) => eprintln!("Error evaluating program: {}", e),
+                    Err(e) => {
+                        eprintln!("{}", e.render(&text));
+                        return ExitCode::FAILURE;
+                    }
                 },
             }
         }
+        Cli::Check(conf) => {
+            let Some(text) = read_target(conf.target, conf.expression) else {
+                return ExitCode::FAILURE;
+            };
+
+            match parse_spanned(&text).and_then(|exp| check(&exp, &TypeEnv::new())) {
+                Ok(ty) => println!("{}", ty),
+                Err(e) => {
+                    eprintln!("{}", e.render(&text));
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
         Cli::Start(conf) => {
             println!("Starting server");
             println!("Directory: {}", conf.directory);
             println!("http://localhost:{}", conf.port);
-            server(conf).unwrap_or_else(|e| eprintln!("Error starting server: {}", e));
+            if let Err(e) = server(conf) {
+                eprintln!("Error starting server: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+        Cli::Repl(conf) => {
+            if let Err(e) = repl(conf) {
+                eprintln!("Error reading input: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// The text of an expression, or of the file containing it.
+fn read_target(target: String, expression: bool) -> Option<String> {
+    if expression {
+        return Some(target);
+    }
+    match fs::read_to_string(target) {
+        Ok(text) => Some(text),
+        Err(e) => {
+            eprintln!("Error reading file: {}", e);
+            None
         }
     }
 }
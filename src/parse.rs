@@ -1,15 +1,19 @@
-use crate::{Exp, Exp::*};
+use crate::{
+    time, Agg, Closure, Column, Error, Exp, Exp::*, Function, Key, Reference, Relation, Span, Type,
+    Version, F64,
+};
 
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take_until, take_while},
-    character::complete::{alpha1, alphanumeric1, char, digit1, multispace1},
-    combinator::{all_consuming, map, map_res, opt, recognize, value},
-    error::Error,
+    bytes::complete::{is_not, tag, take_until, take_while, take_while_m_n},
+    character::complete::{alpha1, alphanumeric1, char, digit1, multispace1, one_of, satisfy},
+    combinator::{all_consuming, map, map_opt, not, opt, recognize, value},
     multi::many0,
-    sequence::{delimited, pair, preceded, tuple},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     Finish, IResult,
 };
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Bexp {
@@ -17,26 +21,50 @@ pub enum Bexp {
     Parens(Box<Bexp>),
     Bool(bool),
     Int(i64),
+    Float(f64),
+    Decimal(Decimal),
     Nil,
+    Null,
+    /// `()`, which only passes no arguments to a function, like `now()`.
+    Unit,
     Str(String),
     Var(String),
+    /// A number that doesn't fit its type, and why. Building the expression
+    /// reports it where it was written.
+    Invalid(String),
+    Spanned(Span, Box<Bexp>),
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Op {
     In,
     Let,
     Select,
+    Rename,
+    Extend,
     Where,
+    On,
     Union,
     Difference,
     Product,
+    Divide,
+    Join,
     Table,
+    Group,
     Item,
+    Assign,
+    Arrow,
     Or,
     Equals,
     And,
+    Is,
+    Like,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
     App,
+    At,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -46,103 +74,676 @@ pub enum Side {
 }
 
 impl Op {
+    /// How tightly the operator binds. Operators on the same level associate
+    /// according to [`Op::assoc`].
+    pub fn precedence(&self) -> u8 {
+        match *self {
+            Op::In => 0,
+            Op::Let => 1,
+            Op::Select => 2,
+            Op::Rename => 3,
+            Op::Extend => 4,
+            Op::Where => 5,
+            Op::On => 6,
+            Op::Union => 7,
+            Op::Difference => 8,
+            Op::Product | Op::Divide => 9,
+            Op::Join => 10,
+            Op::Table => 11,
+            Op::Group => 12,
+            Op::Item => 13,
+            Op::Assign => 14,
+            Op::Arrow => 15,
+            Op::Or => 16,
+            Op::And => 17,
+            Op::Equals | Op::Less | Op::LessEquals | Op::Greater | Op::GreaterEquals => 18,
+            Op::Is | Op::Like => 19,
+            Op::App => 20,
+            Op::At => 21,
+        }
+    }
+
+    /// Whether the operator binds more or less tightly than `other`, or on
+    /// the same level.
+    ///
+    /// Mostly this follows [`Op::precedence`], in which `+`, `-`, `*` and `/`
+    /// bind more loosely than `:` and `,`, since they also combine tables:
+    /// `Staff + id : 3`. A cell or a condition can compare and use `&&` and
+    /// `||` freely: `a : x == 1, y || z`. But arithmetic binds more tightly
+    /// than comparisons, `&&` and `||`, so conditions read as usual:
+    /// `age + 1 > 30 && age < 50`.
+    pub fn compare(&self, other: &Op) -> Ordering {
+        let logic = |op: &Op| {
+            matches!(
+                op,
+                Op::Or
+                    | Op::And
+                    | Op::Equals
+                    | Op::Less
+                    | Op::LessEquals
+                    | Op::Greater
+                    | Op::GreaterEquals
+            )
+        };
+        let arithmetic =
+            |op: &Op| matches!(op, Op::Union | Op::Difference | Op::Product | Op::Divide);
+        if arithmetic(self) && logic(other) {
+            Ordering::Greater
+        } else if logic(self) && arithmetic(other) {
+            Ordering::Less
+        } else {
+            self.precedence().cmp(&other.precedence())
+        }
+    }
+
     pub fn assoc(&self) -> Side {
         match *self {
             Op::In => Side::Right,
             Op::Let => Side::Right,
             Op::Select => Side::Right,
+            Op::Rename => Side::Right,
+            Op::Extend => Side::Right,
             Op::Where => Side::Left,
+            Op::On => Side::Left,
             Op::Union => Side::Left,
             Op::Difference => Side::Left,
             Op::Product => Side::Left,
+            Op::Divide => Side::Left,
+            Op::Join => Side::Left,
             Op::Table => Side::Right,
+            Op::Group => Side::Right,
             Op::Item => Side::Right,
+            Op::Assign => Side::Right,
+            Op::Arrow => Side::Right,
             Op::Or => Side::Left,
             Op::And => Side::Left,
             Op::Equals => Side::Left,
+            Op::Is => Side::Left,
+            Op::Like => Side::Left,
+            Op::Less => Side::Left,
+            Op::LessEquals => Side::Left,
+            Op::Greater => Side::Left,
+            Op::GreaterEquals => Side::Left,
             Op::App => Side::Left,
+            Op::At => Side::Left,
         }
     }
 }
 
-pub fn parse(input: &str) -> Result<Exp, String> {
+pub fn parse(input: &str) -> Result<Exp, Error> {
+    parse_source(input, false)
+}
+
+/// Like [`parse`], but every sub-expression is wrapped in [`Exp::Spanned`] so
+/// that evaluation errors can point back into the source.
+pub fn parse_spanned(input: &str) -> Result<Exp, Error> {
+    parse_source(input, true)
+}
+
+fn parse_source(input: &str, spans: bool) -> Result<Exp, Error> {
+    let source = Source {
+        len: input.len(),
+        spans,
+    };
     match all_consuming(parse_bexp)(input).finish() {
-        Ok((_, bexp)) => parse_exp(bexp),
-        Result::Err(Error { input, code }) => Err(format!("{:?}, input: {:?}", code, input)),
+        Ok((_, bexp)) => parse_exp(bexp, &source),
+        Result::Err(nom::error::Error { input, .. }) => {
+            let error = match input.chars().next() {
+                Some(c) => Error::Parse(format!("unexpected `{}`", c)),
+                None => Error::parse("unexpected end of input"),
+            };
+            let width = input.chars().next().map_or(0, char::len_utf8);
+            Err(error.at(source.span(Span {
+                start: input.len(),
+                end: input.len() - width,
+            })))
+        }
+    }
+}
+
+/// nom only sees the remaining input, so [`Bexp`] spans record the length of
+/// the input left at each end. `Source` converts them to byte offsets.
+struct Source {
+    len: usize,
+    spans: bool,
+}
+
+impl Source {
+    fn span(&self, remaining: Span) -> Span {
+        Span {
+            start: self.len - remaining.start,
+            end: self.len - remaining.end,
+        }
+    }
+
+    fn span_of(&self, bexp: &Bexp) -> Option<Span> {
+        bexp_span(bexp).map(|span| self.span(span))
     }
 }
 
-fn parse_exp(bexp: Bexp) -> Result<Exp, String> {
+fn bexp_span(bexp: &Bexp) -> Option<Span> {
+    match bexp {
+        Bexp::Spanned(span, _) => Some(*span),
+        Bexp::Binary(l, _, r) => match (bexp_span(l), bexp_span(r)) {
+            (Some(l), Some(r)) => Some(Span {
+                start: l.start.max(r.start),
+                end: l.end.min(r.end),
+            }),
+            (l, r) => l.or(r),
+        },
+        Bexp::Parens(bexp) => bexp_span(bexp),
+        _ => None,
+    }
+}
+
+fn parse_exp(bexp: Bexp, source: &Source) -> Result<Exp, Error> {
+    let span = source.span_of(&bexp);
+    let exp = parse_node(bexp, source);
+    match span {
+        Some(span) => {
+            let exp = exp.map_err(|e| e.at(span))?;
+            if source.spans {
+                Ok(Spanned(span, Box::new(exp)))
+            } else {
+                Ok(exp)
+            }
+        }
+        None => exp,
+    }
+}
+
+fn parse_node(bexp: Bexp, source: &Source) -> Result<Exp, Error> {
     match bexp {
         Bexp::Binary(l, op, r) => match op {
             Op::In => match *l {
-                Bexp::Binary(var, Op::Let, exp) => match parse_exp(*var)? {
-                    Var(var) => Ok(Let(
-                        var,
-                        Box::new(parse_exp(*exp)?),
-                        Box::new(parse_exp(*r)?),
-                    )),
-                    exp => Err(format!("expected var, got {:?}", exp)),
+                Bexp::Binary(var, Op::Let, exp) => match unspanned(*var) {
+                    // `f x y = body` defines a function
+                    signature @ Bexp::Binary(_, Op::App, _) => {
+                        let (name, params) = parse_signature(signature)?;
+                        let closure = Box::new(Closure {
+                            name: name.clone(),
+                            params,
+                            body: Box::new(parse_exp(*exp, source)?),
+                            env: vec![],
+                        });
+                        Ok(Let(
+                            name,
+                            Box::new(Exp::Closure(closure)),
+                            Box::new(parse_exp(*r, source)?),
+                        ))
+                    }
+                    var => match parse_node(var, source)? {
+                        Var(var) => Ok(Let(
+                            var,
+                            Box::new(parse_exp(*exp, source)?),
+                            Box::new(parse_exp(*r, source)?),
+                        )),
+                        exp => Err(Error::type_mismatch("variable", &exp)),
+                    },
                 },
-                bexp => Err(format!("expected let, got {:?}", bexp)),
+                _ => Err(Error::parse("expected `=` before `;`")),
+            },
+            Op::Let => Err(Error::parse("let not allowed here")),
+            Op::Select => match *l {
+                Bexp::Binary(keys, Op::Group, aggs) => Ok(Group(
+                    parse_var_list(*keys)?,
+                    parse_agg_list(*aggs)?,
+                    Box::new(parse_exp(*r, source)?),
+                )),
+                l => Ok(Select(parse_var_list(l)?, Box::new(parse_exp(*r, source)?))),
+            },
+            Op::Rename => Ok(Rename(
+                parse_rename_list(*l)?,
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Extend => Ok(Extend(
+                parse_assign_list(*l, source)?,
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Where => Ok(Where(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::On => match *l {
+                Bexp::Binary(left, Op::Join, right) => Ok(JoinOn(
+                    Box::new(parse_exp(*left, source)?),
+                    Box::new(parse_exp(*right, source)?),
+                    Box::new(parse_exp(*r, source)?),
+                )),
+                _ => Err(Error::parse("expected `><` before `on`")),
             },
-            Op::Let => Err("let not allowed here".to_string()),
-            Op::Select => Ok(Select(parse_var_list(*l)?, Box::new(parse_exp(*r)?))),
-            Op::Where => Ok(Where(Box::new(parse_exp(*l)?), Box::new(parse_exp(*r)?))),
-            Op::Union => Ok(Union(Box::new(parse_exp(*l)?), Box::new(parse_exp(*r)?))),
+            Op::Union => Ok(Union(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
             Op::Difference => Ok(Difference(
-                Box::new(parse_exp(*l)?),
-                Box::new(parse_exp(*r)?),
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
             )),
-            Op::Product => Ok(Product(Box::new(parse_exp(*l)?), Box::new(parse_exp(*r)?))),
-            Op::Table => Ok(Table(parse_var_list(*l)?, parse_exp_list(*r)?)),
-            Op::Item => Err("item not allowed here".to_string()),
-            Op::Or => Ok(Or(Box::new(parse_exp(*l)?), Box::new(parse_exp(*r)?))),
-            Op::Equals => Ok(Equals(Box::new(parse_exp(*l)?), Box::new(parse_exp(*r)?))),
-            Op::And => Ok(And(Box::new(parse_exp(*l)?), Box::new(parse_exp(*r)?))),
-            Op::App => match parse_exp(*l)? {
-                Var(var) => match var.as_str() {
-                    "not" => Ok(Not(Box::new(parse_exp(*r)?))),
-                    s => Err(format!("unknown function: {}", s)),
-                },
-                exp => Err(format!("cannot apply {:?}", exp)),
+            Op::Product => Ok(Product(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Divide => Ok(Divide(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Join => Ok(Join(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Table => {
+                let (vars, columns) = parse_header(*l)?;
+                let table = Relation::from_cells(vars, parse_exp_list(*r, source)?)?;
+                Ok(Table(table.declare(columns)?))
+            }
+            Op::Group => Err(Error::parse("group not allowed here")),
+            Op::Item => Err(Error::parse("item not allowed here")),
+            Op::Assign => Err(Error::parse("assignment not allowed here")),
+            Op::Arrow => Err(Error::parse("arrow not allowed here")),
+            Op::Or => Ok(Or(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Equals => Ok(Equals(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Is => {
+                let l = Box::new(parse_exp(*l, source)?);
+                let expected = || Error::parse("expected `null` or `not null` after `is`");
+                match unspanned(*r) {
+                    Bexp::Null => Ok(IsNull(l)),
+                    Bexp::Binary(not, Op::App, null) => match (unspanned(*not), unspanned(*null)) {
+                        (Bexp::Var(not), Bexp::Null) if not == "not" => {
+                            Ok(Not(Box::new(IsNull(l))))
+                        }
+                        _ => Err(expected()),
+                    },
+                    _ => Err(expected()),
+                }
+            }
+            Op::Like => Ok(Like(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::And => Ok(And(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Less => Ok(Less(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::LessEquals => Ok(LessEquals(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::Greater => Ok(Greater(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::GreaterEquals => Ok(GreaterEquals(
+                Box::new(parse_exp(*l, source)?),
+                Box::new(parse_exp(*r, source)?),
+            )),
+            Op::App => {
+                // `f a b` is `(f a) b`: find the function at the bottom
+                let mut args = vec![*r];
+                let mut function = *l;
+                while let Bexp::Binary(l, Op::App, r) = unspanned(function.clone()) {
+                    args.push(*r);
+                    function = *l;
+                }
+                args.reverse();
+                if let [arg] = args.as_slice() {
+                    if unspanned(arg.clone()) == Bexp::Unit {
+                        args.clear();
+                    }
+                }
+                let builtin = match unparenthesised(function.clone()) {
+                    Bexp::Var(var) => Function::lookup(&var),
+                    bexp => {
+                        return Err(Error::type_mismatch("function", &parse_node(bexp, source)?))
+                    }
+                };
+                match builtin {
+                    Some(f) if f.arity() == args.len() => {
+                        let args = args
+                            .into_iter()
+                            .map(|arg| parse_exp(arg, source))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        // Converting a literal string, like `date '2026-10-18'`,
+                        // gives a literal
+                        let literal = match args.as_slice() {
+                            [arg] => match arg.unspanned() {
+                                Str(text) => time::convert(f, text),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        literal.unwrap_or_else(|| Ok(f.apply(args)))
+                    }
+                    Some(f) => Err(Error::Arity {
+                        function: f.name().to_string(),
+                        expected: f.arity(),
+                        found: args.len(),
+                    }),
+                    // Any other function is defined in the program
+                    None => {
+                        let args = args
+                            .into_iter()
+                            .map(|arg| parse_exp(arg, source))
+                            .collect::<Result<_, _>>()?;
+                        Ok(Apply(Box::new(parse_exp(function, source)?), args))
+                    }
+                }
+            }
+            Op::At => match parse_node(*l, source)? {
+                Var(var) => Ok(Exp::Version(var, parse_version(*r, source)?)),
+                exp => Err(Error::type_mismatch("variable", &exp)),
             },
         },
-        Bexp::Parens(bexp) => parse_exp(*bexp),
+        Bexp::Parens(bexp) => parse_node(*bexp, source),
         Bexp::Bool(bool) => Ok(Bool(bool)),
         Bexp::Int(int) => Ok(Int(int)),
-        Bexp::Nil => Ok(Table(vec![], vec![])),
+        Bexp::Float(float) => Ok(Float(F64(float))),
+        Bexp::Decimal(decimal) => Ok(Decimal(decimal)),
+        Bexp::Nil => Ok(Table(Relation::default())),
+        Bexp::Null => Ok(Null),
+        Bexp::Unit => Err(Error::parse("`()` is only allowed after a function")),
+        Bexp::Invalid(message) => Err(Error::Parse(message)),
         Bexp::Str(str) => Ok(Str(str)),
-        Bexp::Var(var) => Ok(Exp::Var(var)),
+        // A function without arguments, like `now`, is applied where it's named
+        Bexp::Var(var) => match Function::lookup(&var) {
+            Some(f) if f.arity() == 0 => Ok(f.apply(vec![])),
+            _ => Ok(Exp::Var(var)),
+        },
+        Bexp::Spanned(_, bexp) => parse_node(*bexp, source),
     }
 }
 
-fn parse_var_list(bexp: Bexp) -> Result<Vec<String>, String> {
-    match bexp {
+/// The name and parameters of a function definition, like `active d`.
+fn parse_signature(bexp: Bexp) -> Result<(String, Vec<String>), Error> {
+    let mut words = vec![];
+    let mut bexp = bexp;
+    while let Bexp::Binary(l, Op::App, r) = unspanned(bexp.clone()) {
+        words.push(*r);
+        bexp = *l;
+    }
+    words.push(bexp);
+    words.reverse();
+    let mut names = vec![];
+    for word in words {
+        match unspanned(word) {
+            Bexp::Var(name) => names.push(name),
+            _ => {
+                return Err(Error::parse(
+                    "expected a function name and parameters before `=`",
+                ))
+            }
+        }
+    }
+    let name = names.remove(0);
+    if Function::lookup(&name).is_some() {
+        return Err(Error::InvalidName(name));
+    }
+    for (i, param) in names.iter().enumerate() {
+        if names[..i].contains(param) {
+            return Err(Error::parse(&format!("parameter `{}` is repeated", param)));
+        }
+    }
+    Ok((name, names))
+}
+
+fn parse_version(bexp: Bexp, source: &Source) -> Result<Version, Error> {
+    let span = source.span_of(&bexp);
+    let version = match unspanned(bexp) {
+        Bexp::Int(n) if n > 0 => Ok(Version::Number(n as u64)),
+        Bexp::Str(text) => {
+            let version = Version::Time(text);
+            match version.time() {
+                Some(_) => Ok(version),
+                None => Err(Error::parse(
+                    "expected a date like '2026-10-01' or a time like '2026-10-01T12:00:00Z'",
+                )),
+            }
+        }
+        _ => Err(Error::parse("expected a version number or time after `@`")),
+    };
+    match span {
+        Some(span) => version.map_err(|e| e.at(span)),
+        None => version,
+    }
+}
+
+fn parse_var_list(bexp: Bexp) -> Result<Vec<String>, Error> {
+    match unspanned(bexp) {
         Bexp::Nil => Ok(vec![]),
         Bexp::Var(var) => Ok(vec![var]),
-        Bexp::Binary(var, Op::Item, vars) => match *var {
+        Bexp::Binary(var, Op::Item, vars) => match unspanned(*var) {
             Bexp::Var(var) => {
                 let mut result = vec![var];
                 result.append(&mut parse_var_list(*vars)?);
                 Ok(result)
             }
-            _ => Err("expected variable".to_string()),
+            _ => Err(Error::parse("expected variable")),
         },
-        _ => Err("expected variables".to_string()),
+        _ => Err(Error::parse("expected variables")),
     }
 }
 
-fn parse_exp_list(bexp: Bexp) -> Result<Vec<Exp>, String> {
+/// The columns of a table literal, each optionally followed by its type and
+/// whether it's a key.
+fn parse_header(bexp: Bexp) -> Result<(Vec<String>, Vec<Column>), Error> {
+    match unspanned(bexp) {
+        Bexp::Nil => Ok((vec![], vec![])),
+        Bexp::Binary(column, Op::Item, columns) => {
+            let (var, column) = parse_column(*column)?;
+            let (mut vars, mut result) = parse_header(*columns)?;
+            vars.insert(0, var);
+            result.insert(0, column);
+            Ok((vars, result))
+        }
+        column => {
+            let (var, column) = parse_column(column)?;
+            Ok((vec![var], vec![column]))
+        }
+    }
+}
+
+fn parse_column(bexp: Bexp) -> Result<(String, Column), Error> {
+    // `id int key` is the name applied to each word in turn
+    let mut words = vec![];
+    let mut bexp = unspanned(bexp);
+    while let Bexp::Binary(l, Op::App, word) = bexp {
+        match unparenthesised(*word) {
+            Bexp::Var(word) => words.push(word),
+            _ => {
+                return Err(Error::parse(
+                    "expected a column like `name`, `name str` or `id int key`",
+                ))
+            }
+        }
+        bexp = unspanned(*l);
+    }
+    let Bexp::Var(var) = bexp else {
+        return Err(Error::parse("expected variable"));
+    };
+
+    let mut column = Column::default();
+    let mut words = words.into_iter().rev();
+    while let Some(word) = words.next() {
+        match (word.as_str(), Type::from_name(&word)) {
+            ("key" | "unique", _) if column.key.is_some() => {
+                return Err(Error::parse(&format!(
+                    "`{}` can only be one of `key` or `unique`",
+                    var
+                )))
+            }
+            ("key", _) => column.key = Some(Key::Primary),
+            ("unique", _) => column.key = Some(Key::Unique),
+            ("references", _) if column.references.is_some() => {
+                return Err(Error::parse(&format!(
+                    "`{}` can only reference one column",
+                    var
+                )))
+            }
+            ("references", _) => {
+                let target = words.next().unwrap_or_default();
+                let Some((target, target_column)) = target.split_once('.') else {
+                    return Err(Error::parse(
+                        "expected a column like `Staff.id` after `references`",
+                    ));
+                };
+                column.references = Some(Reference {
+                    var: target.to_string(),
+                    column: target_column.to_string(),
+                });
+            }
+            (_, Some(_)) if column.is_declared() => {
+                return Err(Error::parse(&format!(
+                    "the type of `{}` must come first, and only once",
+                    var
+                )))
+            }
+            (_, Some(ty)) => column.ty = ty,
+            (_, None) => {
+                return Err(Error::parse(&format!(
+                    "unknown type `{}`, expected int, float, decimal, str, bool, date, time, timestamp, interval or any",
+                    word
+                )))
+            }
+        }
+    }
+    Ok((var, column))
+}
+
+/// Columns like `a := x + 1, b := x > 2`. `:=` binds more tightly than most
+/// of the operators its expressions use, so the list is taken apart into its
+/// atoms and operators again, and split before each `, name :=`.
+fn parse_assign_list(bexp: Bexp, source: &Source) -> Result<Vec<(String, Exp)>, Error> {
+    if unspanned(bexp.clone()) == Bexp::Nil {
+        return Ok(vec![]);
+    }
+    let (mut atoms, mut ops) = (vec![], vec![]);
+    flatten(bexp, &mut atoms, &mut ops);
+    let mut columns = vec![];
+    let mut start = 0;
+    for i in 0..=ops.len() {
+        if i == ops.len() || ops[i] == Op::Item && ops.get(i + 1) == Some(&Op::Assign) {
+            columns.push(parse_assign(&atoms[start..=i], &ops[start..i], source)?);
+            start = i + 1;
+        }
+    }
+    Ok(columns)
+}
+
+fn parse_assign(atoms: &[Bexp], ops: &[Op], source: &Source) -> Result<(String, Exp), Error> {
+    match (atoms, ops) {
+        ([var, first, rest @ ..], [Op::Assign, ops @ ..]) => match unspanned(var.clone()) {
+            Bexp::Var(var) => {
+                let rest = ops.iter().copied().zip(rest.iter().cloned()).collect();
+                let exp = associate(first.clone(), rest);
+                Ok((var, parse_exp(exp, source)?))
+            }
+            _ => Err(Error::parse("expected a column name before `:=`")),
+        },
+        _ => Err(Error::parse("expected a column like `name := exp`")),
+    }
+}
+
+/// The atoms of an expression in order, and the operators between them.
+fn flatten(bexp: Bexp, atoms: &mut Vec<Bexp>, ops: &mut Vec<Op>) {
+    match bexp {
+        Bexp::Binary(l, op, r) => {
+            flatten(*l, atoms, ops);
+            ops.push(op);
+            flatten(*r, atoms, ops);
+        }
+        atom => atoms.push(atom),
+    }
+}
+
+fn parse_rename_list(bexp: Bexp) -> Result<Vec<(String, String)>, Error> {
+    match unspanned(bexp) {
+        Bexp::Nil => Ok(vec![]),
+        Bexp::Binary(rename, Op::Item, renames) => {
+            let mut result = vec![parse_rename(*rename)?];
+            result.append(&mut parse_rename_list(*renames)?);
+            Ok(result)
+        }
+        rename => Ok(vec![parse_rename(rename)?]),
+    }
+}
+
+fn parse_rename(bexp: Bexp) -> Result<(String, String), Error> {
     match bexp {
+        Bexp::Binary(old, Op::Arrow, new) => match (unspanned(*old), unspanned(*new)) {
+            (Bexp::Var(old), Bexp::Var(new)) => Ok((old, new)),
+            _ => Err(Error::parse("expected column names either side of `->`")),
+        },
+        _ => Err(Error::parse("expected a rename like `old -> new`")),
+    }
+}
+
+fn parse_agg_list(bexp: Bexp) -> Result<Vec<Agg>, Error> {
+    match unspanned(bexp) {
         Bexp::Nil => Ok(vec![]),
+        Bexp::Binary(agg, Op::Item, aggs) => {
+            let mut result = vec![parse_agg(*agg)?];
+            result.append(&mut parse_agg_list(*aggs)?);
+            Ok(result)
+        }
+        agg => Ok(vec![parse_agg(agg)?]),
+    }
+}
+
+fn parse_agg(bexp: Bexp) -> Result<Agg, Error> {
+    match unparenthesised(bexp) {
+        Bexp::Var(function) if function == "count" => Ok(Agg::Count),
+        Bexp::Binary(function, Op::App, var) => {
+            match (unparenthesised(*function), unparenthesised(*var)) {
+                (Bexp::Var(function), Bexp::Var(var)) => match function.as_str() {
+                    "sum" => Ok(Agg::Sum(var)),
+                    "min" => Ok(Agg::Min(var)),
+                    "max" => Ok(Agg::Max(var)),
+                    "avg" => Ok(Agg::Avg(var)),
+                    _ => Err(Error::UnknownFunction(function)),
+                },
+                _ => Err(Error::parse("expected an aggregate of a column")),
+            }
+        }
+        _ => Err(Error::parse(
+            "expected an aggregate like `count` or `sum salary`",
+        )),
+    }
+}
+
+fn parse_exp_list(bexp: Bexp, source: &Source) -> Result<Vec<Exp>, Error> {
+    match bexp {
         Bexp::Binary(exp, Op::Item, exps) => {
-            let exp = parse_exp(*exp)?;
+            let exp = parse_exp(*exp, source)?;
             let mut result = vec![exp];
-            result.append(&mut parse_exp_list(*exps)?);
+            result.append(&mut parse_exp_list(*exps, source)?);
             Ok(result)
         }
-        exp => Ok(vec![parse_exp(exp)?]),
+        Bexp::Spanned(_, bexp) if *bexp == Bexp::Nil => Ok(vec![]),
+        exp => Ok(vec![parse_exp(exp, source)?]),
+    }
+}
+
+fn unspanned(bexp: Bexp) -> Bexp {
+    match bexp {
+        Bexp::Spanned(_, bexp) => unspanned(*bexp),
+        bexp => bexp,
+    }
+}
+
+fn unparenthesised(bexp: Bexp) -> Bexp {
+    match unspanned(bexp) {
+        Bexp::Parens(bexp) => unparenthesised(*bexp),
+        bexp => bexp,
     }
 }
 
@@ -152,20 +753,31 @@ fn parse_bexp(input: &str) -> IResult<&str, Bexp> {
     let (input, rest) = many0(pair(preceded(junk, parse_op), preceded(junk, parse_atom)))(input)?;
     let (input, _) = junk(input)?;
 
-    let exp = re_associate(left_associate(first, rest));
+    let exp = associate(first, rest);
 
     Ok((input, exp))
 }
 
 fn parse_atom(input: &str) -> IResult<&str, Bexp> {
-    alt((
+    let (rest, bexp) = alt((
+        parse_unit,
         parse_parens,
         parse_bool,
-        parse_int,
+        parse_number,
         parse_nil,
+        parse_null,
         parse_str,
         parse_var,
-    ))(input)
+    ))(input)?;
+    let span = Span {
+        start: input.len(),
+        end: rest.len(),
+    };
+    Ok((rest, Bexp::Spanned(span, Box::new(bexp))))
+}
+
+fn parse_unit(input: &str) -> IResult<&str, Bexp> {
+    value(Bexp::Unit, tuple((char('('), junk, char(')'))))(input)
 }
 
 fn parse_parens(input: &str) -> IResult<&str, Bexp> {
@@ -181,79 +793,167 @@ fn parse_bool(input: &str) -> IResult<&str, Bexp> {
     ))(input)
 }
 
-fn parse_int(input: &str) -> IResult<&str, Bexp> {
-    map_res(recognize(pair(opt(tag("-")), digit1)), |s: &str| {
-        s.parse().map(Bexp::Int)
-    })(input)
+/// An integer like `-42`, a float like `3.5` or `1e-3`, or an exact decimal
+/// like `3.50d`. A number too large or precise for its type is invalid.
+fn parse_number(input: &str) -> IResult<&str, Bexp> {
+    let int = || recognize(pair(opt(tag("-")), digit1));
+    let fraction = || recognize(pair(char('.'), digit1));
+    let exponent = recognize(tuple((one_of("eE"), opt(one_of("+-")), digit1)));
+    alt((
+        map(
+            terminated(recognize(pair(int(), opt(fraction()))), keyword("d")),
+            |s: &str| match Decimal::from_str_exact(s) {
+                Ok(decimal) => Bexp::Decimal(decimal),
+                Err(_) => Bexp::Invalid(format!(
+                    "`{}d` is too large or precise for a decimal, which holds 28 digits",
+                    s
+                )),
+            },
+        ),
+        map_opt(
+            recognize(tuple((int(), opt(fraction()), opt(exponent)))),
+            |s: &str| match s.parse::<f64>() {
+                // Without a fraction or exponent it's an integer
+                _ if s.bytes().all(|b| b == b'-' || b.is_ascii_digit()) => None,
+                Ok(float) if float.is_finite() => Some(Bexp::Float(float)),
+                _ => Some(Bexp::Invalid(format!("`{}` is too large for a float", s))),
+            },
+        ),
+        map(int(), |s: &str| match s.parse() {
+            Ok(int) => Bexp::Int(int),
+            Err(_) => Bexp::Invalid(format!("`{}` is too large for an integer", s)),
+        }),
+    ))(input)
 }
 
 fn parse_nil(input: &str) -> IResult<&str, Bexp> {
     value(Bexp::Nil, tag("nil"))(input)
 }
 
+fn parse_null(input: &str) -> IResult<&str, Bexp> {
+    value(Bexp::Null, keyword("null"))(input)
+}
+
+/// A string in single quotes. A backslash escapes a quote or another
+/// backslash, or writes `\n`, `\t`, `\r` or any character by its code, like
+/// `\u{e9}`.
 fn parse_str(input: &str) -> IResult<&str, Bexp> {
-    map(delimited(tag("'"), many0(is_not("'")), tag("'")), |s| {
+    let part = alt((
+        map(is_not("'\\"), str::to_string),
+        map(preceded(char('\\'), parse_escape), String::from),
+    ));
+    map(delimited(tag("'"), many0(part), tag("'")), |s| {
         Bexp::Str(s.concat())
     })(input)
 }
 
+fn parse_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        value('\'', char('\'')),
+        value('\\', char('\\')),
+        value('\n', char('n')),
+        value('\t', char('t')),
+        value('\r', char('r')),
+        map_opt(
+            delimited(
+                tag("u{"),
+                take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()),
+                char('}'),
+            ),
+            |hex| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+        ),
+    ))(input)
+}
+
+/// A variable, or a column qualified by its table like `Staff.id`.
 fn parse_var(input: &str) -> IResult<&str, Bexp> {
     map(
-        recognize(pair(
-            alt((alpha1, tag("_"))),
-            many0(alt((alphanumeric1, tag("_")))),
-        )),
+        recognize(pair(identifier, many0(pair(char('.'), identifier)))),
         |s: &str| Bexp::Var(s.to_string()),
     )(input)
 }
 
-fn parse_op(input: &str) -> IResult<&str, Op> {
-    alt((
-        value(Op::In, tag(";")),
-        value(Op::Equals, tag("==")),
-        value(Op::Let, tag("=")),
-        value(Op::Select, tag("<-")),
-        value(Op::Where, tag("?")),
-        value(Op::Union, tag("+")),
-        value(Op::Difference, tag("-")),
-        value(Op::Product, tag("*")),
-        value(Op::Table, tag(":")),
-        value(Op::Item, tag(",")),
-        value(Op::Or, tag("||")),
-        value(Op::And, tag("&&")),
-        value(Op::App, tag("")),
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_")))),
     ))(input)
 }
 
-fn left_associate(first: Bexp, rest: Vec<(Op, Bexp)>) -> Bexp {
-    rest.into_iter().fold(first, |acc, (op, exp)| {
-        Bexp::Binary(Box::new(acc), op, Box::new(exp))
-    })
+/// A word used as an operator, which mustn't run on into a longer name.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(
+        tag(word),
+        not(satisfy(|c: char| c.is_alphanumeric() || c == '_')),
+    )
 }
 
-fn re_associate(exp: Bexp) -> Bexp {
-    // (a l b) r c
+fn parse_op(input: &str) -> IResult<&str, Op> {
+    alt((
+        alt((
+            value(Op::In, tag(";")),
+            value(Op::Equals, tag("==")),
+            value(Op::Let, tag("=")),
+            value(Op::Select, tag("<-")),
+            value(Op::Rename, tag("<~")),
+            value(Op::Extend, tag("<+")),
+            value(Op::LessEquals, tag("<=")),
+            value(Op::Less, tag("<")),
+            value(Op::Arrow, tag("->")),
+            value(Op::Join, tag("><")),
+            value(Op::GreaterEquals, tag(">=")),
+            value(Op::Greater, tag(">")),
+            value(Op::Where, tag("?")),
+            value(Op::On, keyword("on")),
+            value(Op::Is, keyword("is")),
+            value(Op::Like, keyword("like")),
+        )),
+        alt((
+            value(Op::Union, tag("+")),
+            value(Op::Difference, tag("-")),
+            value(Op::Product, tag("*")),
+            value(Op::Divide, tag("/")),
+            value(Op::Assign, tag(":=")),
+            value(Op::Table, tag(":")),
+            value(Op::Group, tag("#")),
+            value(Op::Item, tag(",")),
+            value(Op::Or, tag("||")),
+            value(Op::And, tag("&&")),
+            value(Op::At, tag("@")),
+            value(Op::App, tag("")),
+        )),
+    ))(input)
+}
 
-    let Bexp::Binary(left, r, c) = exp else {
-        return exp;
-    };
-    let c = re_associate(*c);
-    let left = re_associate(*left);
-    let Bexp::Binary(a, l, b) = left else {
-        return Bexp::Binary(Box::new(left), r, Box::new(c));
-    };
+/// Build the tree for atoms separated by operators, binding the operators
+/// as [`Op::compare`] says: an operator waits for every later one that binds
+/// more tightly than it.
+fn associate(first: Bexp, rest: Vec<(Op, Bexp)>) -> Bexp {
+    fn reduce(operands: &mut Vec<Bexp>, op: Op) {
+        let r = operands.pop().expect("a right operand");
+        let l = operands.pop().expect("a left operand");
+        operands.push(Bexp::Binary(Box::new(l), op, Box::new(r)));
+    }
 
-    if r > l || r == l && r.assoc() == Side::Right {
-        // a l (b r c)
-        let left = a;
-        let right = Bexp::Binary(b, r, Box::new(c));
-        re_associate(Bexp::Binary(left, l, Box::new(right)))
-    } else {
-        // (a l b) r c
-        let left = Bexp::Binary(a, l, b);
-        let right = c;
-        Bexp::Binary(Box::new(left), r, Box::new(right))
+    let mut operands = vec![first];
+    let mut pending: Vec<Op> = vec![];
+    for (op, atom) in rest {
+        while let Some(&top) = pending.last() {
+            match top.compare(&op) {
+                Ordering::Greater => {}
+                Ordering::Equal if op.assoc() == Side::Left => {}
+                _ => break,
+            }
+            pending.pop();
+            reduce(&mut operands, top);
+        }
+        pending.push(op);
+        operands.push(atom);
+    }
+    while let Some(op) = pending.pop() {
+        reduce(&mut operands, op);
     }
+    operands.pop().expect("an expression")
 }
 
 fn junk(input: &str) -> IResult<&str, ()> {
@@ -0,0 +1,226 @@
+use crate::{eval::Value, Error, Exp, Type};
+
+use std::{collections::HashSet, fmt};
+
+/// A table: named columns and rows of values. Every row has one value per
+/// column, and no two columns share a name. Each column can also declare
+/// what its values must be, in a [`Column`].
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct Relation {
+    vars: Vec<String>,
+    columns: Vec<Column>,
+    rows: Vec<Vec<Exp>>,
+}
+
+/// What a table header declares about a column, like `id int key`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct Column {
+    /// The type of every value, or [`Type::Unknown`] if it isn't declared.
+    pub ty: Type,
+    pub key: Option<Key>,
+    /// A column of another persisted variable that every value must appear
+    /// in, checked by the server when either is written.
+    pub references: Option<Reference>,
+}
+
+/// A uniqueness constraint. The `key` columns of a table together identify
+/// its rows, and each `unique` column identifies them on its own.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Primary,
+    Unique,
+}
+
+/// A column of a persisted variable, like `Staff.id`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Reference {
+    pub var: String,
+    pub column: String,
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.var, self.column)
+    }
+}
+
+impl Column {
+    /// A column declaring only a type.
+    pub fn typed(ty: Type) -> Column {
+        Column {
+            ty,
+            ..Column::default()
+        }
+    }
+
+    /// Whether the column declares anything.
+    pub fn is_declared(&self) -> bool {
+        *self != Column::default()
+    }
+
+    /// The declarations of this column, falling back on another's for those
+    /// it leaves out.
+    pub fn or(&self, other: &Column) -> Column {
+        Column {
+            ty: match self.ty {
+                Type::Unknown => other.ty.clone(),
+                _ => self.ty.clone(),
+            },
+            key: self.key.or(other.key),
+            references: self.references.clone().or(other.references.clone()),
+        }
+    }
+}
+
+impl Relation {
+    pub fn new(vars: Vec<String>, rows: Vec<Vec<Exp>>) -> Result<Relation, Error> {
+        if let Some(var) = duplicate(&vars) {
+            return Err(Error::DuplicateColumn(var.clone()));
+        }
+        if let Some(row) = rows.iter().find(|row| row.len() != vars.len()) {
+            return Err(Error::RowLength {
+                columns: vars,
+                found: row.len(),
+            });
+        }
+        let columns = vec![Column::default(); vars.len()];
+        Ok(Relation {
+            vars,
+            columns,
+            rows,
+        })
+    }
+
+    /// Declare what the columns hold, checking every row keeps to it.
+    pub fn declare(self, columns: Vec<Column>) -> Result<Relation, Error> {
+        for row in &self.rows {
+            for ((var, column), exp) in self.vars.iter().zip(&columns).zip(row) {
+                if !Type::of(exp).matches(&column.ty) {
+                    return Err(Error::TypeMismatch {
+                        expected: format!("{} in column `{}`", column.ty.describe(), var),
+                        found: exp.describe().to_string(),
+                    });
+                }
+            }
+        }
+
+        let primary = (0..columns.len())
+            .filter(|&i| columns[i].key == Some(Key::Primary))
+            .collect::<Vec<_>>();
+        for &i in &primary {
+            if self.rows.iter().any(|row| row[i] == Exp::Null) {
+                return Err(Error::TypeMismatch {
+                    expected: format!("a value in key column `{}`", self.vars[i]),
+                    found: "null".to_string(),
+                });
+            }
+        }
+        let unique = (0..columns.len())
+            .filter(|&i| columns[i].key == Some(Key::Unique))
+            .map(|i| vec![i]);
+        for key in unique.chain((!primary.is_empty()).then_some(primary)) {
+            let mut seen = HashSet::new();
+            // Unique columns can hold any number of nulls
+            for row in self
+                .rows
+                .iter()
+                .filter(|row| key.iter().all(|&i| row[i] != Exp::Null))
+            {
+                let values = key.iter().map(|&i| Value(&row[i])).collect::<Vec<_>>();
+                if !seen.insert(values) {
+                    return Err(Error::DuplicateKey(
+                        key.iter()
+                            .map(|&i| (self.vars[i].clone(), row[i].clone()))
+                            .collect(),
+                    ));
+                }
+            }
+        }
+
+        Ok(Relation { columns, ..self })
+    }
+
+    /// Split the values of a table literal, written one row after another,
+    /// into rows.
+    pub fn from_cells(vars: Vec<String>, cells: Vec<Exp>) -> Result<Relation, Error> {
+        let rest = cells.len().checked_rem(vars.len()).unwrap_or(cells.len());
+        if rest != 0 {
+            return Err(Error::RowLength {
+                columns: vars,
+                found: rest,
+            });
+        }
+        let rows = cells
+            .chunks(vars.len().max(1))
+            .map(<[Exp]>::to_vec)
+            .collect();
+        Relation::new(vars, rows)
+    }
+
+    pub fn vars(&self) -> &[String] {
+        &self.vars
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Whether any column declares anything.
+    pub fn is_declared(&self) -> bool {
+        self.columns.iter().any(Column::is_declared)
+    }
+
+    /// The columns that reference other variables.
+    pub fn references(&self) -> impl Iterator<Item = (&String, &Reference)> {
+        self.vars
+            .iter()
+            .zip(&self.columns)
+            .filter_map(|(var, column)| Some((var, column.references.as_ref()?)))
+    }
+
+    pub fn rows(&self) -> &[Vec<Exp>] {
+        &self.rows
+    }
+
+    pub fn into_parts(self) -> (Vec<String>, Vec<Vec<Exp>>) {
+        (self.vars, self.rows)
+    }
+
+    /// The same table with each value replaced.
+    pub fn map_cells(self, f: impl Fn(Exp) -> Exp) -> Relation {
+        let rows = self
+            .rows
+            .into_iter()
+            .map(|row| row.into_iter().map(&f).collect())
+            .collect();
+        Relation { rows, ..self }
+    }
+
+    /// The values of every row, one row after another.
+    pub fn cells(&self) -> impl Iterator<Item = &Exp> {
+        self.rows.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The position of a column.
+    pub fn index(&self, var: &str) -> Result<usize, Error> {
+        self.vars
+            .iter()
+            .position(|v| v == var)
+            .ok_or_else(|| Error::UnknownColumn(var.to_string()))
+    }
+}
+
+fn duplicate(vars: &[String]) -> Option<&String> {
+    vars.iter()
+        .enumerate()
+        .find(|(i, var)| vars[..*i].contains(var))
+        .map(|(_, var)| var)
+}
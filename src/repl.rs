@@ -0,0 +1,272 @@
+use crate::{
+    check, client, parse, parse_spanned, read_eval, serialise, type_env, Env, Exp, Repl, Response,
+    Type,
+};
+
+use rustyline::{
+    completion::Completer,
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Editor, Helper,
+};
+use std::{fs, path::PathBuf};
+
+const HELP: &str = "\
+Enter an expression to evaluate it. End a definition with `;` and leave the
+body off to keep the variable for later lines; press enter on an empty line
+to finish input that ends in `;`.
+
+:env          list the variables defined in this session
+:type <exp>   show the type of an expression
+:load <file>  evaluate a file, keeping its definitions
+:help         show this message
+:quit         leave the session";
+
+pub fn repl(conf: Repl) -> rustyline::Result<()> {
+    let mut editor = Editor::<Input, DefaultHistory>::new()?;
+    editor.set_helper(Some(Input));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    let mut session = Session::new(conf.server);
+    loop {
+        match editor.readline("sdb> ") {
+            Ok(line) => {
+                let input = line.trim();
+                if input.is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(input)?;
+                if input == ":quit" || input == ":q" {
+                    break;
+                }
+                match session.input(input) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => println!("{}", output),
+                    Err(message) => eprintln!("{}", message),
+                }
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+/// The state of an interactive session: the variables defined so far, or the
+/// server that holds them.
+pub struct Session {
+    server: Option<String>,
+    env: Env,
+}
+
+impl Session {
+    pub fn new(server: Option<String>) -> Session {
+        Session {
+            server,
+            env: Env::new(),
+        }
+    }
+
+    /// Handle one complete input, returning what to print.
+    pub fn input(&mut self, input: &str) -> Result<String, String> {
+        let Some(command) = input.strip_prefix(':') else {
+            return self.run(input);
+        };
+        let (command, arg) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(command, arg)| (command, arg.trim()));
+        match command {
+            "help" | "h" => Ok(HELP.to_string()),
+            "env" => self.env(),
+            "type" | "t" => self.type_of(arg),
+            "load" | "l" => {
+                let text = fs::read_to_string(arg).map_err(|e| format!("{}: {}", arg, e))?;
+                self.run(&text)
+            }
+            _ => Err(format!("unknown command `:{}`, try :help", command)),
+        }
+    }
+
+    fn env(&self) -> Result<String, String> {
+        if self.server.is_some() {
+            return Err(":env isn't available when connected to a server".to_string());
+        }
+        let mut vars = self.env.iter().collect::<Vec<_>>();
+        vars.sort_by_key(|(var, _)| *var);
+        Ok(vars
+            .into_iter()
+            .map(|(var, exp)| format!("{} : {}", var, Type::of(exp)))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// The type of an expression. Local expressions are checked without
+    /// evaluating them; the server has the values, so it evaluates them.
+    fn type_of(&mut self, text: &str) -> Result<String, String> {
+        if self.server.is_some() {
+            let (result, _) = self.eval(text)?;
+            return Ok(Type::of(&result).to_string());
+        }
+        let exp = parse_spanned(text).map_err(|e| e.render(text))?;
+        let ty = check(&exp, &type_env(&self.env)).map_err(|e| e.render(text))?;
+        Ok(ty.to_string())
+    }
+
+    /// Evaluate a program, printing nothing if it only defines variables.
+    fn run(&mut self, text: &str) -> Result<String, String> {
+        let (result, binding) = self.eval(text)?;
+        Ok(if binding {
+            String::new()
+        } else {
+            serialise(result)
+        })
+    }
+
+    fn eval(&mut self, text: &str) -> Result<(Exp, bool), String> {
+        let binding = ends_with_semicolon(text);
+        let text = if binding {
+            format!("{}\nnil", text)
+        } else {
+            text.to_string()
+        };
+        let result = match &self.server {
+            Some(url) => match client(&text, url) {
+                Ok(Response::Ok(result)) => parse(&result).map_err(|e| e.to_string())?,
+                Ok(Response::Err { message, .. }) => return Err(message),
+                Err(e) => return Err(format!("Error running client: {}", e)),
+            },
+            None => {
+                let (result, env) = read_eval(&text, &self.env).map_err(|e| e.render(&text))?;
+                self.env = env;
+                result
+            }
+        };
+        Ok((result, binding))
+    }
+}
+
+/// Whether input is ready to evaluate. Input with unclosed parentheses or
+/// comments is never complete, and input ending in `;` is complete once it
+/// ends with an empty line.
+pub fn is_complete(text: &str) -> bool {
+    let scan = scan(text);
+    if scan.depth > 0 || scan.open {
+        return false;
+    }
+    !scan.semicolon || text.trim_end_matches([' ', '\t']).ends_with('\n')
+}
+
+fn ends_with_semicolon(text: &str) -> bool {
+    scan(text).semicolon
+}
+
+struct Scan {
+    /// Unclosed parentheses.
+    depth: i32,
+    /// Whether the text ends inside a string or multi-line comment.
+    open: bool,
+    /// Whether the last token is `;`.
+    semicolon: bool,
+}
+
+fn scan(text: &str) -> Scan {
+    let mut scan = Scan {
+        depth: 0,
+        open: false,
+        semicolon: false,
+    };
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("--") {
+            rest = rest.find('\n').map_or("", |i| &rest[i..]);
+            continue;
+        }
+        if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(i) => rest = &comment[i + 2..],
+                None => {
+                    scan.open = true;
+                    rest = "";
+                }
+            }
+            continue;
+        }
+        if let Some(string) = rest.strip_prefix('\'') {
+            scan.semicolon = false;
+            match string_end(string) {
+                Some(i) => rest = &string[i + 1..],
+                None => {
+                    scan.open = true;
+                    rest = "";
+                }
+            }
+            continue;
+        }
+        match c {
+            '(' => scan.depth += 1,
+            ')' => scan.depth -= 1,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            scan.semicolon = c == ';';
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    scan
+}
+
+/// The index of the quote that ends a string, skipping escaped characters.
+fn string_end(string: &str) -> Option<usize> {
+    let mut chars = string.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".sdb_history"))
+}
+
+struct Input;
+
+impl Helper for Input {}
+
+impl Completer for Input {
+    type Candidate = String;
+}
+
+impl Hinter for Input {
+    type Hint = String;
+}
+
+impl Highlighter for Input {}
+
+impl Validator for Input {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if input.starts_with(':') || is_complete(input) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
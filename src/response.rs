@@ -0,0 +1,29 @@
+#[derive(Debug, PartialEq, Clone)]
+pub enum Response {
+    Ok(String),
+    Err { category: String, message: String },
+}
+
+impl Response {
+    pub fn encode(&self) -> String {
+        match self {
+            Response::Ok(result) => format!("ok\n{}", result),
+            Response::Err { category, message } => format!("error {}\n{}", category, message),
+        }
+    }
+
+    pub fn decode(text: &str) -> Response {
+        let (status, body) = text.split_once('\n').unwrap_or((text, ""));
+        match status.split_once(' ') {
+            None if status == "ok" => Response::Ok(body.to_string()),
+            Some(("error", category)) => Response::Err {
+                category: category.to_string(),
+                message: body.to_string(),
+            },
+            _ => Response::Err {
+                category: "protocol".to_string(),
+                message: format!("malformed response: {:?}", text),
+            },
+        }
+    }
+}
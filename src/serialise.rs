@@ -1,29 +1,59 @@
 use crate::{
-    Bexp,
+    time, Agg, Bexp, Column,
     Exp::{self, *},
-    Op, Side,
+    Key, Op, Side, Type, Version,
 };
 
+use std::cmp::Ordering;
+
 pub fn serialise(exp: Exp) -> String {
     serialise_bexp(serialise_exp(exp))
 }
 
 fn serialise_exp(exp: Exp) -> Bexp {
     match exp {
-        Let(var, exp, body) => Bexp::Binary(
-            Box::new(Bexp::Binary(
-                Box::new(Bexp::Var(var)),
-                Op::Let,
-                Box::new(with_parens(*exp, Op::Let, Side::Right)),
-            )),
-            Op::In,
-            Box::new(with_parens(*body, Op::In, Side::Right)),
-        ),
+        Let(var, exp, body) => match *exp {
+            // A function that captured nothing but old versions is written as
+            // its definition
+            Closure(closure)
+                if closure.name == var && closure.env.iter().all(|(var, _)| is_version(var)) =>
+            {
+                let_in(
+                    signature(&closure),
+                    with_parens(*closure.body, Op::Let, Side::Right),
+                    with_parens(*body, Op::In, Side::Right),
+                )
+            }
+            exp => let_in(
+                Bexp::Var(var),
+                with_parens(exp, Op::Let, Side::Right),
+                with_parens(*body, Op::In, Side::Right),
+            ),
+        },
         Select(l, r) => Bexp::Binary(
             Box::new(serialise_var_list(l)),
             Op::Select,
             Box::new(with_parens(*r, Op::Select, Side::Right)),
         ),
+        Rename(renames, r) => Bexp::Binary(
+            Box::new(serialise_rename_list(renames)),
+            Op::Rename,
+            Box::new(with_parens(*r, Op::Rename, Side::Right)),
+        ),
+        Extend(columns, r) => Bexp::Binary(
+            Box::new(serialise_assign_list(columns)),
+            Op::Extend,
+            Box::new(with_parens(*r, Op::Extend, Side::Right)),
+        ),
+        Group(keys, aggs, r) => Bexp::Binary(
+            Box::new(Bexp::Binary(
+                Box::new(serialise_var_list(keys)),
+                Op::Group,
+                Box::new(serialise_agg_list(aggs)),
+            )),
+            Op::Select,
+            Box::new(with_parens(*r, Op::Select, Side::Right)),
+        ),
         Where(l, r) => Bexp::Binary(
             Box::new(with_parens(*l, Op::Where, Side::Left)),
             Op::Where,
@@ -44,14 +74,35 @@ fn serialise_exp(exp: Exp) -> Bexp {
             Op::Product,
             Box::new(with_parens(*r, Op::Product, Side::Right)),
         ),
-        Table(vars, exps) => {
-            if vars.is_empty() && exps.is_empty() {
+        Divide(l, r) => Bexp::Binary(
+            Box::new(with_parens(*l, Op::Divide, Side::Left)),
+            Op::Divide,
+            Box::new(with_parens(*r, Op::Divide, Side::Right)),
+        ),
+        Join(l, r) => Bexp::Binary(
+            Box::new(with_parens(*l, Op::Join, Side::Left)),
+            Op::Join,
+            Box::new(with_parens(*r, Op::Join, Side::Right)),
+        ),
+        JoinOn(l, r, cond) => Bexp::Binary(
+            Box::new(Bexp::Binary(
+                Box::new(with_parens(*l, Op::Join, Side::Left)),
+                Op::Join,
+                Box::new(with_parens(*r, Op::Join, Side::Right)),
+            )),
+            Op::On,
+            Box::new(with_parens(*cond, Op::On, Side::Right)),
+        ),
+        Table(table) => {
+            if table.vars().is_empty() && table.is_empty() {
                 Bexp::Nil
             } else {
+                let columns = table.columns().to_vec();
+                let (vars, rows) = table.into_parts();
                 Bexp::Binary(
-                    Box::new(serialise_var_list(vars)),
+                    Box::new(serialise_header(vars, columns)),
                     Op::Table,
-                    Box::new(serialise_exp_list(exps)),
+                    Box::new(serialise_exp_list(rows.into_iter().flatten().collect())),
                 )
             }
         }
@@ -70,15 +121,137 @@ fn serialise_exp(exp: Exp) -> Bexp {
             Op::And,
             Box::new(with_parens(*r, Op::And, Side::Right)),
         ),
-        Not(exp) => Bexp::Binary(
-            Box::new(Bexp::Var("not".to_string())),
+        Less(l, r) => Bexp::Binary(
+            Box::new(with_parens(*l, Op::Less, Side::Left)),
+            Op::Less,
+            Box::new(with_parens(*r, Op::Less, Side::Right)),
+        ),
+        LessEquals(l, r) => Bexp::Binary(
+            Box::new(with_parens(*l, Op::LessEquals, Side::Left)),
+            Op::LessEquals,
+            Box::new(with_parens(*r, Op::LessEquals, Side::Right)),
+        ),
+        Greater(l, r) => Bexp::Binary(
+            Box::new(with_parens(*l, Op::Greater, Side::Left)),
+            Op::Greater,
+            Box::new(with_parens(*r, Op::Greater, Side::Right)),
+        ),
+        GreaterEquals(l, r) => Bexp::Binary(
+            Box::new(with_parens(*l, Op::GreaterEquals, Side::Left)),
+            Op::GreaterEquals,
+            Box::new(with_parens(*r, Op::GreaterEquals, Side::Right)),
+        ),
+        // `not (x is null)` reads better as `x is not null`
+        Not(exp) => match unspanned(*exp) {
+            IsNull(exp) => Bexp::Binary(
+                Box::new(with_parens(*exp, Op::Is, Side::Left)),
+                Op::Is,
+                Box::new(Bexp::Binary(
+                    Box::new(Bexp::Var("not".to_string())),
+                    Op::App,
+                    Box::new(Bexp::Null),
+                )),
+            ),
+            exp => Bexp::Binary(
+                Box::new(Bexp::Var("not".to_string())),
+                Op::App,
+                Box::new(with_parens(exp, Op::App, Side::Left)),
+            ),
+        },
+        IsNull(exp) => Bexp::Binary(
+            Box::new(with_parens(*exp, Op::Is, Side::Left)),
+            Op::Is,
+            Box::new(Bexp::Null),
+        ),
+        Like(l, r) => Bexp::Binary(
+            Box::new(with_parens(*l, Op::Like, Side::Left)),
+            Op::Like,
+            Box::new(with_parens(*r, Op::Like, Side::Right)),
+        ),
+        Call(f, args) => args
+            .into_iter()
+            .fold(Bexp::Var(f.name().to_string()), |acc, arg| {
+                Bexp::Binary(
+                    Box::new(acc),
+                    Op::App,
+                    Box::new(with_parens(arg, Op::App, Side::Right)),
+                )
+            }),
+        // `f ()` applies a function to no arguments
+        Apply(f, args) if args.is_empty() => Bexp::Binary(
+            Box::new(with_parens(*f, Op::App, Side::Left)),
+            Op::App,
+            Box::new(Bexp::Unit),
+        ),
+        Apply(f, args) => {
+            args.into_iter()
+                .fold(with_parens(*f, Op::App, Side::Left), |acc, arg| {
+                    Bexp::Binary(
+                        Box::new(acc),
+                        Op::App,
+                        Box::new(with_parens(arg, Op::App, Side::Right)),
+                    )
+                })
+        }
+        // A function is written as its definition, after the values it
+        // captured: `x = 1; f y = x + y; f`
+        Closure(closure) => {
+            let definition = let_in(
+                signature(&closure),
+                with_parens(*closure.body, Op::Let, Side::Right),
+                Bexp::Var(closure.name),
+            );
+            closure
+                .env
+                .into_iter()
+                .filter(|(var, _)| !is_version(var))
+                .rev()
+                .fold(definition, |body, (var, exp)| {
+                    let_in(Bexp::Var(var), with_parens(exp, Op::Let, Side::Right), body)
+                })
+        }
+        Schema(exp) => Bexp::Binary(
+            Box::new(Bexp::Var("schema".to_string())),
             Op::App,
             Box::new(with_parens(*exp, Op::App, Side::Left)),
         ),
+        Null => Bexp::Null,
         Bool(bool) => Bexp::Bool(bool),
         Int(int) => Bexp::Int(int),
+        Float(float) => Bexp::Float(float.0),
+        Decimal(decimal) => Bexp::Decimal(decimal),
+        Date(_) | Time(_) | Timestamp(_) | Interval(_) => {
+            let (f, text) = time::format(&exp).expect("a date or time");
+            Bexp::Binary(
+                Box::new(Bexp::Var(f.name().to_string())),
+                Op::App,
+                Box::new(Bexp::Str(text)),
+            )
+        }
         Str(str) => Bexp::Str(str),
         Var(var) => Bexp::Var(var),
+        Exp::Version(var, version) => Bexp::Binary(
+            Box::new(Bexp::Var(var)),
+            Op::At,
+            Box::new(match version {
+                Version::Number(n) => Bexp::Int(n as i64),
+                Version::Time(text) => Bexp::Str(text),
+            }),
+        ),
+        Spanned(_, exp) => serialise_exp(*exp),
+    }
+}
+
+/// Whether a captured name is an old version, like `Staff@1`. Those aren't
+/// written with a function, since its body reads them again by name.
+fn is_version(var: &str) -> bool {
+    var.contains('@')
+}
+
+fn unspanned(exp: Exp) -> Exp {
+    match exp {
+        Spanned(_, exp) => unspanned(*exp),
+        exp => exp,
     }
 }
 
@@ -93,6 +266,95 @@ fn serialise_var_list(mut vars: Vec<String>) -> Bexp {
     }
 }
 
+fn serialise_header(vars: Vec<String>, columns: Vec<Column>) -> Bexp {
+    let serialise_column = |(var, column): (String, Column)| {
+        let ty = match column.ty {
+            Type::Unknown => None,
+            ty => Some(ty.name()),
+        };
+        let key = column.key.map(|key| match key {
+            Key::Primary => "key",
+            Key::Unique => "unique",
+        });
+        let references = column
+            .references
+            .map(|reference| ["references".to_string(), reference.to_string()]);
+        let words = ty.into_iter().chain(key).map(str::to_string);
+        words
+            .chain(references.into_iter().flatten())
+            .fold(Bexp::Var(var), |acc, word| {
+                Bexp::Binary(Box::new(acc), Op::App, Box::new(Bexp::Var(word)))
+            })
+    };
+    let mut columns = vars.into_iter().zip(columns);
+    match columns.next() {
+        Some(first) => columns.fold(serialise_column(first), |acc, column| {
+            Bexp::Binary(Box::new(acc), Op::Item, Box::new(serialise_column(column)))
+        }),
+        None => Bexp::Nil,
+    }
+}
+
+fn serialise_assign_list(columns: Vec<(String, Exp)>) -> Bexp {
+    // The parser splits the list into columns before applying precedence,
+    // so only expressions that would end the list or split it need
+    // parentheses
+    let serialise_assign = |(var, exp)| {
+        let bexp = match serialise_exp(exp) {
+            Bexp::Binary(l, op, r)
+                if op.precedence() > Op::Extend.precedence()
+                    && op.precedence() < Op::Table.precedence() =>
+            {
+                Bexp::Binary(l, op, r)
+            }
+            bexp => parenthesise(bexp, Op::Assign, Side::Right),
+        };
+        Bexp::Binary(Box::new(Bexp::Var(var)), Op::Assign, Box::new(bexp))
+    };
+    let mut columns = columns.into_iter();
+    match columns.next() {
+        Some(first) => columns.fold(serialise_assign(first), |acc, column| {
+            Bexp::Binary(Box::new(acc), Op::Item, Box::new(serialise_assign(column)))
+        }),
+        None => Bexp::Nil,
+    }
+}
+
+fn serialise_rename_list(renames: Vec<(String, String)>) -> Bexp {
+    let serialise_rename = |(old, new)| {
+        Bexp::Binary(
+            Box::new(Bexp::Var(old)),
+            Op::Arrow,
+            Box::new(Bexp::Var(new)),
+        )
+    };
+    let mut renames = renames.into_iter();
+    match renames.next() {
+        Some(first) => renames.fold(serialise_rename(first), |acc, rename| {
+            Bexp::Binary(Box::new(acc), Op::Item, Box::new(serialise_rename(rename)))
+        }),
+        None => Bexp::Nil,
+    }
+}
+
+fn serialise_agg_list(aggs: Vec<Agg>) -> Bexp {
+    let serialise_agg = |agg: Agg| match agg.column() {
+        Some(var) => Bexp::Binary(
+            Box::new(Bexp::Var(agg.function().to_string())),
+            Op::App,
+            Box::new(Bexp::Var(var.to_string())),
+        ),
+        None => Bexp::Var(agg.function().to_string()),
+    };
+    let mut aggs = aggs.into_iter();
+    match aggs.next() {
+        Some(first) => aggs.fold(serialise_agg(first), |acc, agg| {
+            Bexp::Binary(Box::new(acc), Op::Item, Box::new(serialise_agg(agg)))
+        }),
+        None => Bexp::Nil,
+    }
+}
+
 fn serialise_exp_list(mut exps: Vec<Exp>) -> Bexp {
     if exps.is_empty() {
         Bexp::Nil
@@ -108,16 +370,50 @@ fn serialise_exp_list(mut exps: Vec<Exp>) -> Bexp {
     }
 }
 
+/// The name and parameters of a function, like `f x y`.
+fn signature(closure: &crate::Closure) -> Bexp {
+    closure
+        .params
+        .iter()
+        .fold(Bexp::Var(closure.name.clone()), |acc, param| {
+            Bexp::Binary(Box::new(acc), Op::App, Box::new(Bexp::Var(param.clone())))
+        })
+}
+
+fn let_in(var: Bexp, exp: Bexp, body: Bexp) -> Bexp {
+    Bexp::Binary(
+        Box::new(Bexp::Binary(Box::new(var), Op::Let, Box::new(exp))),
+        Op::In,
+        Box::new(body),
+    )
+}
+
 fn with_parens(exp: Exp, parent: Op, side: Side) -> Bexp {
-    let bexp = serialise_exp(exp);
+    parenthesise(serialise_exp(exp), parent, side)
+}
+
+fn parenthesise(bexp: Bexp, parent: Op, side: Side) -> Bexp {
     match bexp {
         Bexp::Binary(_, op, _) => {
-            if op < parent || op == parent && op.assoc() != side {
+            let parens = match op.compare(&parent) {
+                Ordering::Less => true,
+                Ordering::Equal => op.assoc() != side,
+                Ordering::Greater => false,
+            };
+            if parens {
                 Bexp::Parens(Box::new(bexp))
             } else {
                 bexp
             }
         }
+        // `f -1` would read as a subtraction
+        Bexp::Int(int) if parent == Op::App && int < 0 => Bexp::Parens(Box::new(bexp)),
+        Bexp::Float(float) if parent == Op::App && float.is_sign_negative() => {
+            Bexp::Parens(Box::new(bexp))
+        }
+        Bexp::Decimal(decimal) if parent == Op::App && decimal.is_sign_negative() => {
+            Bexp::Parens(Box::new(bexp))
+        }
         _ => bexp,
     }
 }
@@ -133,10 +429,37 @@ fn serialise_bexp(exp: Bexp) -> String {
         Bexp::Parens(bexp) => format!("({})", serialise_bexp(*bexp),),
         Bexp::Bool(bool) => bool.to_string(),
         Bexp::Int(int) => int.to_string(),
+        // The shortest form that reads back as the same float, always with a
+        // `.` or an exponent
+        Bexp::Float(float) => format!("{:?}", float),
+        // Decimals keep their scale, so `1.50d` stays `1.50d`
+        Bexp::Decimal(decimal) => format!("{}d", decimal),
         Bexp::Nil => "nil".to_string(),
-        Bexp::Str(str) => format!("'{}'", str),
+        Bexp::Null => "null".to_string(),
+        Bexp::Unit => "()".to_string(),
+        Bexp::Invalid(message) => unreachable!("serialising an invalid number: {}", message),
+        Bexp::Str(str) => format!("'{}'", escape(&str)),
         Bexp::Var(var) => var,
+        Bexp::Spanned(_, bexp) => serialise_bexp(*bexp),
+    }
+}
+
+/// Escape a string so it reads back the same between quotes. Control
+/// characters without a short escape are written by their code.
+fn escape(str: &str) -> String {
+    let mut escaped = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '\'' => escaped.push_str("\\'"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
     }
+    escaped
 }
 
 fn serialise_op(op: Op) -> &'static str {
@@ -144,15 +467,30 @@ fn serialise_op(op: Op) -> &'static str {
         Op::In => "; ",
         Op::Let => " = ",
         Op::Select => " <- ",
+        Op::Rename => " <~ ",
+        Op::Extend => " <+ ",
         Op::Where => " ? ",
+        Op::On => " on ",
         Op::Union => " + ",
         Op::Difference => " - ",
         Op::Product => " * ",
+        Op::Divide => " / ",
+        Op::Join => " >< ",
         Op::Table => " : ",
+        Op::Group => " # ",
         Op::Item => ", ",
+        Op::Assign => " := ",
+        Op::Arrow => " -> ",
         Op::Or => " || ",
         Op::Equals => " == ",
+        Op::Is => " is ",
+        Op::Like => " like ",
         Op::And => " && ",
+        Op::Less => " < ",
+        Op::LessEquals => " <= ",
+        Op::Greater => " > ",
+        Op::GreaterEquals => " >= ",
         Op::App => " ",
+        Op::At => "@",
     }
 }
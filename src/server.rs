@@ -1,6 +1,13 @@
-use crate::{eval, parse, serialise, Env, Exp, Server};
+use crate::{
+    check,
+    eval::Value,
+    eval_with,
+    exp::{analyse_reads, analyse_versions},
+    parse_spanned, serialise, type_env, Env, Error, Exp, Relation, Response, Semantics, Server,
+    Store,
+};
 
-use std::{collections::HashSet, fs, io, net::SocketAddr, sync::Arc};
+use std::{collections::HashSet, hash::Hash, io, net::SocketAddr, sync::Arc};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
@@ -11,54 +18,133 @@ pub async fn server(conf: Server) -> io::Result<()> {
     let addr = SocketAddr::from(([127, 0, 0, 1], conf.port));
     let listener = TcpListener::bind(addr).await?;
 
-    fs::create_dir_all(&conf.directory)?;
+    let store = Arc::new(Store::new(&conf.directory));
+    store.open().await?;
 
     let conf = Arc::new(conf);
 
     loop {
         let (stream, _) = listener.accept().await?;
         let conf = Arc::clone(&conf);
+        let store = Arc::clone(&store);
 
         tokio::spawn(async move {
-            handle_connection(stream, conf).await.unwrap_or_else(|e| {
-                eprintln!("Error handling connection: {}", e);
-            });
+            handle_connection(stream, conf, store)
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!("Error handling connection: {}", e);
+                });
         });
     }
 }
 
-async fn handle_connection(mut stream: TcpStream, conf: Arc<Server>) -> Result<(), String> {
+async fn handle_connection(
+    mut stream: TcpStream,
+    conf: Arc<Server>,
+    store: Arc<Store>,
+) -> io::Result<()> {
     let mut text = String::new();
-    stream
-        .read_to_string(&mut text)
-        .await
-        .map_err(|e| e.to_string())?;
-    let parsed = parse(&text)?;
+    stream.read_to_string(&mut text).await?;
 
-    let reads = analyse_reads(&parsed, &empty());
-    let env = read_env(&conf.directory, &reads).await?;
+    if conf.verbose {
+        println!();
+        println!("Input: {}", text.trim());
+    }
+
+    let response = match execute(&text, &conf, &store).await {
+        Ok(result) => Response::Ok(serialise(result)),
+        Err(e) => Response::Err {
+            category: e.category().to_string(),
+            message: e.render(&text),
+        },
+    };
+
+    stream.write_all(response.encode().as_bytes()).await?;
+
+    if conf.verbose {
+        match &response {
+            Response::Ok(result) => println!("Result: {}", result),
+            Response::Err { message, .. } => println!("{}", message),
+        }
+    }
+
+    Ok(())
+}
 
-    let (result, env) = eval(&parsed, &env)?;
+/// Run a program against the store, persisting the variables it defines.
+pub async fn execute(text: &str, conf: &Server, store: &Store) -> Result<Exp, Error> {
+    let parsed = parse_spanned(text)?;
 
+    let reads = analyse_reads(&parsed, &empty());
+    let versions = analyse_versions(&parsed);
     let writes = analyse_writes(&parsed);
-    let env = env
+    let locked = union(
+        reads.clone(),
+        versions.iter().map(|(var, _)| var.clone()).collect(),
+    );
+
+    // Writes also lock the tables whose references they could break, and
+    // reading a function also reads the variables it uses. Which variables
+    // those are can change while waiting for the locks, so check again once
+    // they're held.
+    let (_locks, reads) = loop {
+        let extra = related(&writes, store);
+        let all_reads = union(reads.clone(), used(&reads, store));
+        let locks = store
+            .lock(
+                &union(union(locked.clone(), all_reads.clone()), extra.clone()),
+                &writes,
+            )
+            .await;
+        if related(&writes, store).is_subset(&extra) && used(&reads, store).is_subset(&all_reads) {
+            break (locks, all_reads);
+        }
+    };
+
+    // The functions read can refer to old versions too
+    let mut env = store.read_env(&reads).await?;
+    let versions = env
+        .values()
+        .filter_map(|exp| match exp {
+            Exp::Closure(closure) => Some(closure.versions()),
+            _ => None,
+        })
+        .fold(versions, union);
+    env.extend(store.read_versions(&versions).await?);
+    let env = reclose(&env);
+
+    // Reject badly typed programs before anything is evaluated or written
+    check(&parsed, &type_env(&env))?;
+
+    let semantics = if conf.bag {
+        Semantics::Bag
+    } else {
+        Semantics::Set
+    };
+    let (result, written) = eval_with(&parsed, &env, semantics)?;
+
+    let mut written = written
         .into_iter()
         .filter(|(k, _)| writes.contains(k))
-        .collect();
-    write_env(&conf.directory, &env)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let response = serialise(result);
-    stream
-        .write_all(response.as_bytes())
-        .await
-        .map_err(|e| e.to_string())?;
+        .collect::<Env>();
+    unclose(&mut written, &env);
+    for (var, exp) in written.iter_mut() {
+        let old = match env.get(var) {
+            Some(old) => old.clone(),
+            None => match store.read(var).await {
+                Ok(old) => old,
+                Err(Error::UnknownVariable(_)) => continue,
+                Err(e) => return Err(e),
+            },
+        };
+        if let Exp::Table(old) = old {
+            *exp = conform(var, &old, exp.clone())?;
+        }
+    }
+    check_references(&written, store).await?;
+    store.write_env(&written).await?;
 
     if conf.verbose {
-        println!();
-        println!("Input: {}", serialise(parsed));
-        println!("Result: {}", response);
         println!(
             "Reads: {}",
             reads.into_iter().collect::<Vec<_>>().join(", ")
@@ -69,58 +155,179 @@ async fn handle_connection(mut stream: TcpStream, conf: Arc<Server>) -> Result<(
         );
     }
 
-    Ok(())
+    Ok(result)
 }
 
-async fn read_env(dir: &str, reads: &HashSet<String>) -> Result<Env, String> {
-    let mut env = Env::new();
-    for filename in reads {
-        let path = format!("{}/{}", dir, filename);
-        let text = tokio::fs::read_to_string(&path)
-            .await
-            .map_err(|e| e.to_string())?;
-        let exp = parse(&text)?;
-        env.insert(filename.clone(), exp);
-    }
-    Ok(env)
+/// A new value for a table that declares column types or keys must have the
+/// same columns, and keeps to the declarations unless it makes its own.
+fn conform(var: &str, old: &Relation, new: Exp) -> Result<Exp, Error> {
+    if !old.is_declared() {
+        return Ok(new);
+    }
+    let Exp::Table(new) = new else {
+        return Err(Error::type_mismatch("table", &new));
+    };
+    if new.vars() != old.vars() {
+        return Err(Error::ColumnMismatch {
+            op: format!("assignment to `{}`", var),
+            left: old.vars().to_vec(),
+            right: new.vars().to_vec(),
+        });
+    }
+    let columns = new
+        .columns()
+        .iter()
+        .zip(old.columns())
+        .map(|(new, old)| new.or(old))
+        .collect();
+    Ok(Exp::Table(new.declare(columns)?))
+}
+
+/// The variables that the stored functions among some variables use,
+/// directly or through other functions.
+fn used(vars: &HashSet<String>, store: &Store) -> HashSet<String> {
+    let mut used = HashSet::new();
+    let mut next = vars.clone();
+    while !next.is_empty() {
+        next = next
+            .iter()
+            .flat_map(|var| store.uses(var))
+            .filter(|var| !used.contains(var))
+            .collect();
+        used.extend(next.iter().cloned());
+    }
+    used
+}
+
+/// A function refers to the persisted variables it uses instead of keeping
+/// their values, so that it sees their latest values whenever it's read.
+/// It keeps any values that aren't the ones being persisted, like those of
+/// variables shadowed later in the program.
+fn unclose(written: &mut Env, env: &Env) {
+    let persisted = |var: &str, exp: &Exp| written.get(var).or_else(|| env.get(var)) == Some(exp);
+    let drops = written
+        .iter()
+        .filter_map(|(var, exp)| match exp {
+            Exp::Closure(closure) => Some((
+                var.clone(),
+                closure
+                    .env
+                    .iter()
+                    .filter(|(var, exp)| persisted(var, exp))
+                    .map(|(var, _)| var.clone())
+                    .collect::<HashSet<_>>(),
+            )),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (var, drop) in drops {
+        if let Some(Exp::Closure(closure)) = written.get_mut(&var) {
+            closure.env.retain(|(var, _)| !drop.contains(var));
+        }
+    }
+}
+
+/// Give the functions read from the store the values of the variables they
+/// use, which are read along with them.
+fn reclose(env: &Env) -> Env {
+    let mut closed = Env::new();
+    for var in env.keys() {
+        close(var, env, &mut closed, &mut HashSet::new());
+    }
+    closed
+}
+
+fn close(var: &str, env: &Env, closed: &mut Env, visiting: &mut HashSet<String>) -> Option<Exp> {
+    if let Some(exp) = closed.get(var) {
+        return Some(exp.clone());
+    }
+    let exp = env.get(var)?;
+    let Exp::Closure(closure) = exp else {
+        closed.insert(var.to_string(), exp.clone());
+        return Some(exp.clone());
+    };
+    // A function can't be given itself, even through other functions
+    if !visiting.insert(var.to_string()) {
+        return None;
+    }
+    let mut closure = closure.clone();
+    for used in closure.captures() {
+        if let Some(exp) = close(&used, env, closed, visiting) {
+            closure.env.push((used, exp));
+        }
+    }
+    closure.env.sort_by(|(l, _), (r, _)| l.cmp(r));
+    visiting.remove(var);
+    let exp = Exp::Closure(closure);
+    closed.insert(var.to_string(), exp.clone());
+    Some(exp)
 }
 
-async fn write_env(dir: &str, env: &Env) -> io::Result<()> {
-    for (filename, exp) in env {
-        let path = format!("{}/{}", dir, filename);
-        let serialised = serialise(exp.clone());
-        tokio::fs::write(path, serialised).await?;
+/// The variables that written variables reference or are referenced by.
+fn related(writes: &HashSet<String>, store: &Store) -> HashSet<String> {
+    writes
+        .iter()
+        .flat_map(|var| union(store.targets(var), store.referrers(var)))
+        .collect()
+}
+
+/// Check the references of every written table, and of every table that
+/// references one, against the values they will have once written.
+async fn check_references(written: &Env, store: &Store) -> Result<(), Error> {
+    let mut vars = written.keys().cloned().collect::<HashSet<_>>();
+    for var in written.keys() {
+        vars.extend(store.referrers(var));
+    }
+    for var in vars {
+        let Some(Exp::Table(table)) = latest(&var, written, store).await? else {
+            continue;
+        };
+        for (column, reference) in table.references() {
+            let target = match latest(&reference.var, written, store).await? {
+                Some(Exp::Table(target)) => target,
+                Some(exp) => return Err(Error::type_mismatch("table", &exp)),
+                None => return Err(Error::UnknownVariable(reference.var.clone())),
+            };
+            let j = target.index(&reference.column)?;
+            let values = target
+                .rows()
+                .iter()
+                .map(|row| Value(&row[j]))
+                .collect::<HashSet<_>>();
+            let i = table.index(column)?;
+            // A null refers to nothing, so it's always allowed
+            let missing = table
+                .rows()
+                .iter()
+                .find(|row| row[i] != Exp::Null && !values.contains(&Value(&row[i])));
+            if let Some(row) = missing {
+                return Err(Error::MissingReference {
+                    column: format!("{}.{}", var, column),
+                    value: row[i].clone(),
+                    target: reference.to_string(),
+                });
+            }
+        }
     }
     Ok(())
 }
 
-fn analyse_reads(exp: &Exp, defined: &HashSet<String>) -> HashSet<String> {
-    match exp {
-        Exp::Let(var, exp, body) => union(
-            analyse_reads(exp, defined),
-            analyse_reads(body, &union(single(var), defined.clone())),
-        ),
-        Exp::Select(_, r) => analyse_reads(r, defined),
-        Exp::Where(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
-        Exp::Union(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
-        Exp::Difference(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
-        Exp::Product(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
-        Exp::Table(_, r) => r
-            .iter()
-            .flat_map(|exp| analyse_reads(exp, defined))
-            .collect(),
-        Exp::Or(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
-        Exp::Equals(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
-        Exp::And(l, r) => union(analyse_reads(l, defined), analyse_reads(r, defined)),
-        Exp::Not(exp) => analyse_reads(exp, defined),
-        Exp::Var(var) if !defined.contains(var) => single(var),
-        _ => empty(),
+/// The value a variable will have once the written variables are stored.
+async fn latest(var: &str, written: &Env, store: &Store) -> Result<Option<Exp>, Error> {
+    match written.get(var) {
+        Some(exp) => Ok(Some(exp.clone())),
+        None => match store.read(var).await {
+            Ok(exp) => Ok(Some(exp)),
+            Err(Error::UnknownVariable(_)) => Ok(None),
+            Err(e) => Err(e),
+        },
     }
 }
 
 fn analyse_writes(exp: &Exp) -> HashSet<String> {
     match exp {
         Exp::Let(var, _, body) => union(single(var), analyse_writes(body)),
+        Exp::Spanned(_, exp) => analyse_writes(exp),
         _ => empty(),
     }
 }
@@ -133,6 +340,6 @@ fn single(s: &str) -> HashSet<String> {
     HashSet::from([s.to_string()])
 }
 
-fn union(a: HashSet<String>, b: HashSet<String>) -> HashSet<String> {
+fn union<T: Eq + Hash + Clone>(a: HashSet<T>, b: HashSet<T>) -> HashSet<T> {
     a.union(&b).cloned().collect()
 }
@@ -0,0 +1,548 @@
+use crate::{eval, exp::parse_time, parse, serialise, Env, Error, Exp, Version};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock},
+};
+
+const TEMP_EXTENSION: &str = "tmp";
+const JOURNAL_EXTENSION: &str = "journal";
+const LEGACY_EXTENSION: &str = "legacy";
+
+/// Leaves room for the suffixes of temporary files within the usual 255 byte
+/// limit on file names.
+const MAX_NAME_LEN: usize = 200;
+
+const RESERVED_NAMES: [&str; 22] = [
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// The on-disk home of persisted variables. Each variable has a directory,
+/// named by [`encode_name`], holding every value it has been given: writing a
+/// variable adds a new numbered version rather than replacing the old one.
+/// Each version file starts with a comment recording when it was written.
+///
+/// All the variables written by one program are committed together. Their new
+/// values are first staged in temporary files, then a journal listing the
+/// renames that install them is written. Once the journal is durable the
+/// transaction is committed, and the renames are replayed from it if the
+/// server stops before finishing them, or before the next write if they fail.
+///
+/// Programs lock the variables they read and write with [`Store::lock`] so
+/// that concurrent programs behave as if they ran one after another.
+///
+/// The store also remembers which variables each variable's latest value
+/// references, so that writes can find the tables whose references they
+/// might break, and which variables each stored function uses, so that
+/// programs can read them along with it.
+pub struct Store {
+    dir: PathBuf,
+    counter: AtomicU64,
+    locks: Arc<Mutex<HashMap<String, Arc<RwLock<()>>>>>,
+    references: Mutex<HashMap<String, HashSet<String>>>,
+    uses: Mutex<HashMap<String, HashSet<String>>>,
+    /// Committed transactions whose renames failed, with the values they
+    /// write.
+    unfinished: tokio::sync::Mutex<Vec<(PathBuf, Env)>>,
+}
+
+/// Locks held on behalf of one program, released when dropped. The store
+/// only keeps a variable's lock while some program holds or waits for it.
+pub struct Locks {
+    vars: Vec<String>,
+    reads: Vec<OwnedRwLockReadGuard<()>>,
+    writes: Vec<OwnedRwLockWriteGuard<()>>,
+    locks: Arc<Mutex<HashMap<String, Arc<RwLock<()>>>>>,
+}
+
+impl Drop for Locks {
+    fn drop(&mut self) {
+        self.reads.clear();
+        self.writes.clear();
+        // Locks are only handed out with the map locked, so one that only
+        // the map holds can't be in use
+        let mut locks = self.locks.lock().unwrap();
+        for var in &self.vars {
+            if locks
+                .get(var)
+                .is_some_and(|lock| Arc::strong_count(lock) == 1)
+            {
+                locks.remove(var);
+            }
+        }
+    }
+}
+
+impl Store {
+    pub fn new(dir: impl Into<PathBuf>) -> Store {
+        Store {
+            dir: dir.into(),
+            counter: AtomicU64::new(0),
+            locks: Arc::new(Mutex::new(HashMap::new())),
+            references: Mutex::new(HashMap::new()),
+            uses: Mutex::new(HashMap::new()),
+            unfinished: tokio::sync::Mutex::new(vec![]),
+        }
+    }
+
+    /// Take shared locks on the variables a program reads and exclusive locks
+    /// on the ones it writes. Locks are always taken in name order, so two
+    /// programs can't deadlock waiting on each other.
+    pub async fn lock(&self, reads: &HashSet<String>, writes: &HashSet<String>) -> Locks {
+        let vars = reads.union(writes).collect::<BTreeSet<_>>();
+        let mut locks = Locks {
+            vars: vec![],
+            reads: vec![],
+            writes: vec![],
+            locks: Arc::clone(&self.locks),
+        };
+        for var in vars {
+            let lock = Arc::clone(self.locks.lock().unwrap().entry(var.clone()).or_default());
+            locks.vars.push(var.clone());
+            if writes.contains(var) {
+                locks.writes.push(lock.write_owned().await);
+            } else {
+                locks.reads.push(lock.read_owned().await);
+            }
+        }
+        locks
+    }
+
+    /// Create the directory if needed, finish any committed transactions
+    /// whose renames were interrupted, remove the staged files of
+    /// transactions that never committed, and find the references between
+    /// variables.
+    pub async fn open(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        for journal in self.entries(JOURNAL_EXTENSION).await? {
+            self.replay(&journal).await?;
+        }
+        for temp in self.entries(TEMP_EXTENSION).await? {
+            fs::remove_file(temp).await?;
+        }
+        self.migrate().await?;
+
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(var) = decode_name(&name) else {
+                continue;
+            };
+            // An aborted first write can leave a directory with no versions
+            match self.read(&var).await {
+                Ok(exp) => self.index(&var, &exp),
+                Err(Error::UnknownVariable(_)) => {}
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The variables a variable's latest value references.
+    pub fn targets(&self, var: &str) -> HashSet<String> {
+        let references = self.references.lock().unwrap();
+        references.get(var).cloned().unwrap_or_default()
+    }
+
+    /// The variables whose latest values reference a variable.
+    pub fn referrers(&self, var: &str) -> HashSet<String> {
+        let references = self.references.lock().unwrap();
+        references
+            .iter()
+            .filter(|(_, targets)| targets.contains(var))
+            .map(|(referrer, _)| referrer.clone())
+            .collect()
+    }
+
+    /// The variables that a variable's latest value uses, if it's a function.
+    pub fn uses(&self, var: &str) -> HashSet<String> {
+        let uses = self.uses.lock().unwrap();
+        uses.get(var).cloned().unwrap_or_default()
+    }
+
+    fn index(&self, var: &str, exp: &Exp) {
+        let targets = match exp {
+            Exp::Table(table) => table
+                .references()
+                .map(|(_, reference)| reference.var.clone())
+                .collect(),
+            _ => HashSet::new(),
+        };
+        self.references
+            .lock()
+            .unwrap()
+            .insert(var.to_string(), targets);
+        let uses = match exp {
+            Exp::Closure(closure) => closure.uses(),
+            _ => HashSet::new(),
+        };
+        self.uses.lock().unwrap().insert(var.to_string(), uses);
+    }
+
+    /// Upgrade older layouts of the directory: files named after variables
+    /// verbatim are renamed to their encoded names, and single files holding a
+    /// variable's value become the first version in its directory.
+    async fn migrate(&self) -> io::Result<()> {
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let legacy = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if legacy && decode_name(&name).is_none() {
+                if let Ok(encoded) = encode_name(&name) {
+                    fs::rename(entry.path(), self.dir.join(encoded)).await?;
+                }
+            }
+        }
+
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type().await?.is_file() && decode_name(&name).is_some() {
+                let path = self.dir.join(format!("{}.{}", name, LEGACY_EXTENSION));
+                fs::rename(entry.path(), path).await?;
+            }
+        }
+        for path in self.entries(LEGACY_EXTENSION).await? {
+            let dir = path.with_extension("");
+            fs::create_dir_all(&dir).await?;
+            fs::rename(path, dir.join("1")).await?;
+        }
+        sync_dir(&self.dir).await
+    }
+
+    /// Read the latest version of a variable.
+    pub async fn read(&self, var: &str) -> Result<Exp, Error> {
+        match self.versions(var).await?.last() {
+            Some(&n) => self.read_file(var, n).await,
+            None => Err(Error::UnknownVariable(var.to_string())),
+        }
+    }
+
+    /// Read an earlier version of a variable, either by number or as it was
+    /// at a point in time.
+    pub async fn read_version(&self, var: &str, version: &Version) -> Result<Exp, Error> {
+        let unknown = || Error::UnknownVariable(format!("{}@{}", var, version));
+        let versions = self.versions(var).await?;
+        let n = match version {
+            Version::Number(n) => versions.contains(n).then_some(*n),
+            Version::Time(_) => {
+                let time = version.time().ok_or_else(unknown)?;
+                let mut found = None;
+                for &n in &versions {
+                    match self.written(var, n).await? {
+                        Some(written) if written > time => break,
+                        _ => found = Some(n),
+                    }
+                }
+                found
+            }
+        };
+        self.read_file(var, n.ok_or_else(unknown)?).await
+    }
+
+    /// The version numbers of a variable, in ascending order.
+    async fn versions(&self, var: &str) -> Result<Vec<u64>, Error> {
+        let dir = self.dir.join(encode_name(var)?);
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(Error::Io(format!("{}: {}", var, e))),
+        };
+        let mut versions = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            if let Ok(n) = entry.file_name().to_string_lossy().parse() {
+                versions.push(n);
+            }
+        }
+        versions.sort();
+        Ok(versions)
+    }
+
+    async fn read_file(&self, var: &str, n: u64) -> Result<Exp, Error> {
+        let path = self.dir.join(encode_name(var)?).join(n.to_string());
+        let text = fs::read_to_string(path)
+            .await
+            .map_err(|e| Error::Io(format!("{}@{}: {}", var, n, e)))?;
+        // A function is stored as its definition, which evaluates to it
+        let exp = match parse(&text) {
+            Ok(exp @ Exp::Let(..)) => eval(&exp, &Env::new()).map(|(exp, _)| exp),
+            result => result,
+        };
+        exp.map_err(|e| Error::Io(format!("{}@{}: {}", var, n, e)))
+    }
+
+    /// When a version was written, from its header. Versions migrated from
+    /// before history was kept have no header.
+    async fn written(&self, var: &str, n: u64) -> Result<Option<DateTime<Utc>>, Error> {
+        let path = self.dir.join(encode_name(var)?).join(n.to_string());
+        let text = fs::read_to_string(path)
+            .await
+            .map_err(|e| Error::Io(format!("{}@{}: {}", var, n, e)))?;
+        let header = text.lines().next().unwrap_or("");
+        Ok(header
+            .strip_prefix("-- version ")
+            .and_then(|header| header.split_once(", written "))
+            .and_then(|(_, time)| parse_time(time)))
+    }
+
+    /// Read the variables that exist. Free variables of a program aren't
+    /// necessarily persisted, since they include column names used in `?`
+    /// conditions, so missing ones are left for `eval` to report.
+    pub async fn read_env(&self, reads: &HashSet<String>) -> Result<Env, Error> {
+        let mut env = Env::new();
+        for var in reads {
+            match self.read(var).await {
+                Ok(exp) => {
+                    env.insert(var.clone(), exp);
+                }
+                Err(Error::UnknownVariable(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(env)
+    }
+
+    /// Read earlier versions of variables, bound as `var@version`.
+    pub async fn read_versions(&self, versions: &HashSet<(String, Version)>) -> Result<Env, Error> {
+        let mut env = Env::new();
+        for (var, version) in versions {
+            let exp = self.read_version(var, version).await?;
+            env.insert(format!("{}@{}", var, version), exp);
+        }
+        Ok(env)
+    }
+
+    pub async fn write(&self, var: &str, exp: &Exp) -> Result<(), Error> {
+        self.write_env(&Env::from([(var.to_string(), exp.clone())]))
+            .await
+    }
+
+    /// Add new versions of several variables as one transaction: after a
+    /// crash either all of them have their new values or none of them do.
+    pub async fn write_env(&self, env: &Env) -> Result<(), Error> {
+        if env.is_empty() {
+            return Ok(());
+        }
+        self.finish().await?;
+
+        let mut renames = vec![];
+        let journal = self.unique_path("transaction", JOURNAL_EXTENSION);
+        let committed = match self.stage(env, &mut renames).await {
+            Ok(()) => self.commit(&journal, &renames).await.map_err(Error::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = committed {
+            self.abort(&renames).await;
+            return Err(e);
+        }
+
+        // The transaction is committed, so if its renames fail they're
+        // finished from the journal before anything else is written
+        let applied = match self.apply(&renames).await {
+            Ok(()) => fs::remove_file(&journal).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = applied {
+            self.unfinished.lock().await.push((journal, env.clone()));
+            return Err(e.into());
+        }
+        for (var, exp) in env {
+            self.index(var, exp);
+        }
+        Ok(())
+    }
+
+    /// Write each new value to a synced temporary file, recording the rename
+    /// that will install it as the variable's next version.
+    async fn stage(&self, env: &Env, renames: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), Error> {
+        let written = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        for (var, exp) in env {
+            let name = encode_name(var)?;
+            let n = self.versions(var).await?.last().map_or(1, |n| n + 1);
+            let dir = self.dir.join(&name);
+            fs::create_dir_all(&dir).await?;
+
+            let temp = self.unique_path(&name, TEMP_EXTENSION);
+            renames.push((temp.clone(), dir.join(n.to_string())));
+            let text = format!(
+                "-- version {}, written {}\n{}",
+                n,
+                written,
+                serialise(exp.clone())
+            );
+            write_synced(&temp, text.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Durably record the renames of a transaction. This is the commit point.
+    async fn commit(&self, journal: &Path, renames: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+        let text = renames
+            .iter()
+            .map(|(temp, path)| format!("{} {}\n", self.relative(temp), self.relative(path)))
+            .collect::<String>();
+        let temp = self.unique_path("transaction", TEMP_EXTENSION);
+        let result = write_synced(&temp, text.as_bytes()).await;
+        let result = match result {
+            Ok(()) => fs::rename(&temp, journal).await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&temp).await;
+        }
+        result?;
+        sync_dir(&self.dir).await
+    }
+
+    /// Finish the committed transactions whose renames failed. Until that
+    /// succeeds no more writes are accepted, since they'd reuse the version
+    /// numbers those transactions hold.
+    async fn finish(&self) -> io::Result<()> {
+        let mut unfinished = self.unfinished.lock().await;
+        while let Some((journal, env)) = unfinished.last() {
+            self.replay(journal).await?;
+            for (var, exp) in env {
+                self.index(var, exp);
+            }
+            unfinished.pop();
+        }
+        Ok(())
+    }
+
+    /// Finish a committed transaction by doing the renames its journal lists
+    /// that haven't been done yet.
+    async fn replay(&self, journal: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(journal).await?;
+        let renames = text
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(temp, path)| (self.dir.join(temp), self.dir.join(path)))
+            .filter(|(temp, _)| temp.exists())
+            .collect::<Vec<_>>();
+        self.apply(&renames).await?;
+        fs::remove_file(journal).await
+    }
+
+    async fn apply(&self, renames: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+        for (temp, path) in renames {
+            fs::rename(temp, path).await?;
+            if let Some(dir) = path.parent() {
+                sync_dir(dir).await?;
+            }
+        }
+        sync_dir(&self.dir).await
+    }
+
+    async fn abort(&self, renames: &[(PathBuf, PathBuf)]) {
+        for (temp, _) in renames {
+            let _ = fs::remove_file(temp).await;
+        }
+    }
+
+    async fn entries(&self, extension: &str) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == extension) {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn unique_path(&self, stem: &str, extension: &str) -> PathBuf {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        self.dir.join(format!("{}.{}.{}", stem, n, extension))
+    }
+}
+
+/// Map a variable name to a file name that stays inside the directory and
+/// can't collide with another variable's, even on case-insensitive file
+/// systems. Lowercase ASCII letters, digits and `_` are kept, uppercase
+/// letters are written as `^` followed by the lowercase letter, and every
+/// other byte is written as `%` followed by two lowercase hex digits. Encoded
+/// names never contain `.`, which keeps them apart from temporary files.
+pub fn encode_name(var: &str) -> Result<String, Error> {
+    if var.is_empty() {
+        return Err(Error::InvalidName(var.to_string()));
+    }
+    let mut name = String::new();
+    for byte in var.bytes() {
+        match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'_' => name.push(byte as char),
+            b'A'..=b'Z' => {
+                name.push('^');
+                name.push(byte.to_ascii_lowercase() as char);
+            }
+            _ => name.push_str(&format!("%{:02x}", byte)),
+        }
+    }
+    if RESERVED_NAMES.contains(&name.as_str()) {
+        name = format!("%{:02x}{}", name.as_bytes()[0], &name[1..]);
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidName(var.to_string()));
+    }
+    Ok(name)
+}
+
+/// The inverse of [`encode_name`], or `None` if the file name isn't one it
+/// produces.
+pub fn decode_name(name: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            'a'..='z' | '0'..='9' | '_' => bytes.push(c as u8),
+            '^' => match chars.next()? {
+                c @ 'a'..='z' => bytes.push(c.to_ascii_uppercase() as u8),
+                _ => return None,
+            },
+            '%' => {
+                let hex = [chars.next()?, chars.next()?].iter().collect::<String>();
+                if hex.chars().any(|c| c.is_ascii_uppercase()) {
+                    return None;
+                }
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+    let var = String::from_utf8(bytes).ok()?;
+    (encode_name(&var).ok()? == name).then_some(var)
+}
+
+async fn write_synced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create(path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await
+}
+
+/// Make renames in the directory durable. Directories can't be opened for
+/// syncing on every platform, so this is only done on unix.
+async fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        fs::File::open(dir).await?.sync_all().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use crate::{exp::parse_time, Error, Exp, Function, Type};
+
+use chrono::{Datelike, NaiveDate, NaiveTime, SecondsFormat, TimeDelta};
+use std::ops::RangeInclusive;
+
+const DAY: i64 = 24 * 60 * 60;
+const YEARS: RangeInclusive<i32> = 0..=9999;
+
+/// An arithmetic operator that works on dates, times and intervals.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TimeOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+/// Which operand of [`result_type`] has the wrong type.
+pub(crate) enum Mismatch {
+    Left,
+    /// The right operand, and what was expected instead.
+    Right(&'static str),
+}
+
+pub(crate) fn is_time(ty: &Type) -> bool {
+    matches!(
+        ty,
+        Type::Date | Type::Time | Type::Timestamp | Type::Interval
+    )
+}
+
+/// The type of arithmetic where either operand is a date, time, timestamp or
+/// interval. Dates and times move by intervals, subtracting two of the same
+/// kind gives an interval, and intervals can be multiplied by integers.
+pub(crate) fn result_type(op: TimeOp, l: &Type, r: &Type) -> Result<Type, Mismatch> {
+    use TimeOp::*;
+    match (op, l, r) {
+        (Add, Type::Date | Type::Time | Type::Timestamp, Type::Interval) => Ok(l.clone()),
+        (Add, Type::Interval, r) if is_time(r) => Ok(r.clone()),
+        (Sub, l, Type::Interval) if is_time(l) => Ok(l.clone()),
+        (Sub, Type::Date | Type::Time | Type::Timestamp, r) if r == l => Ok(Type::Interval),
+        (Mul, Type::Interval, Type::Int) | (Mul, Type::Int, Type::Interval) => Ok(Type::Interval),
+        (Add, Type::Interval, _) => Err(Mismatch::Right("date, time, timestamp or interval")),
+        (Sub, Type::Date, _) => Err(Mismatch::Right("interval or date")),
+        (Sub, Type::Time, _) => Err(Mismatch::Right("interval or time")),
+        (Sub, Type::Timestamp, _) => Err(Mismatch::Right("interval or timestamp")),
+        (Mul, Type::Interval, _) => Err(Mismatch::Right("integer")),
+        (Mul, Type::Int, _) => Err(Mismatch::Right("number")),
+        (_, l, _) if is_time(l) => Err(Mismatch::Right("interval")),
+        (_, l, _) if l.is_number() => Err(Mismatch::Right("number")),
+        _ => Err(Mismatch::Left),
+    }
+}
+
+/// Do arithmetic on values that [`result_type`] accepts. Dates stay within
+/// the years 0 to 9999, which their literals can write.
+pub(crate) fn apply(op: TimeOp, l: &Exp, r: &Exp) -> Result<Exp, Error> {
+    match calculate(op, l, r)? {
+        Exp::Date(date) if !YEARS.contains(&date.year()) => Err(out_of_range()),
+        Exp::Timestamp(time) if !YEARS.contains(&time.year()) => Err(out_of_range()),
+        exp => Ok(exp),
+    }
+}
+
+fn calculate(op: TimeOp, l: &Exp, r: &Exp) -> Result<Exp, Error> {
+    use TimeOp::*;
+    match (op, l, r) {
+        (Add, Exp::Date(date), Exp::Interval(i)) | (Add, Exp::Interval(i), Exp::Date(date)) => {
+            add_days(*date, *i)
+        }
+        (Sub, Exp::Date(date), Exp::Interval(i)) => add_days(*date, -*i),
+        (Add, Exp::Time(time), Exp::Interval(i)) | (Add, Exp::Interval(i), Exp::Time(time)) => {
+            Ok(Exp::Time(time.overflowing_add_signed(*i).0))
+        }
+        (Sub, Exp::Time(time), Exp::Interval(i)) => {
+            Ok(Exp::Time(time.overflowing_sub_signed(*i).0))
+        }
+        (Add, Exp::Timestamp(t), Exp::Interval(i)) | (Add, Exp::Interval(i), Exp::Timestamp(t)) => {
+            t.checked_add_signed(*i)
+                .map(Exp::Timestamp)
+                .ok_or_else(out_of_range)
+        }
+        (Sub, Exp::Timestamp(t), Exp::Interval(i)) => t
+            .checked_sub_signed(*i)
+            .map(Exp::Timestamp)
+            .ok_or_else(out_of_range),
+        (Add, Exp::Interval(l), Exp::Interval(r)) => {
+            l.checked_add(r).map(Exp::Interval).ok_or_else(out_of_range)
+        }
+        (Sub, Exp::Interval(l), Exp::Interval(r)) => {
+            l.checked_sub(r).map(Exp::Interval).ok_or_else(out_of_range)
+        }
+        (Sub, Exp::Date(l), Exp::Date(r)) => Ok(Exp::Interval(l.signed_duration_since(*r))),
+        (Sub, Exp::Time(l), Exp::Time(r)) => Ok(Exp::Interval(l.signed_duration_since(*r))),
+        (Sub, Exp::Timestamp(l), Exp::Timestamp(r)) => {
+            Ok(Exp::Interval(l.signed_duration_since(*r)))
+        }
+        (Mul, Exp::Interval(i), Exp::Int(n)) | (Mul, Exp::Int(n), Exp::Interval(i)) => {
+            i32::try_from(*n)
+                .ok()
+                .and_then(|n| i.checked_mul(n))
+                .map(Exp::Interval)
+                .ok_or_else(out_of_range)
+        }
+        (op, l, r) => unreachable!("{:?} on {} and {}", op, l.describe(), r.describe()),
+    }
+}
+
+fn add_days(date: NaiveDate, interval: TimeDelta) -> Result<Exp, Error> {
+    if interval.num_seconds() % DAY != 0 || interval.subsec_nanos() != 0 {
+        return Err(Error::Arithmetic(
+            "can't add part of a day to a date".to_string(),
+        ));
+    }
+    date.checked_add_signed(interval)
+        .map(Exp::Date)
+        .ok_or_else(out_of_range)
+}
+
+fn out_of_range() -> Error {
+    Error::Arithmetic("date or time out of range".to_string())
+}
+
+/// Read the text of a date, time, timestamp or interval, as the function of
+/// the same name does. Other functions aren't conversions.
+pub(crate) fn convert(f: Function, text: &str) -> Option<Result<Exp, Error>> {
+    let (value, example) = match f {
+        Function::Date => (
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .map(Exp::Date),
+            "a date like '2026-10-18'",
+        ),
+        Function::Time => (
+            NaiveTime::parse_from_str(text, "%H:%M:%S%.f")
+                .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M"))
+                .ok()
+                .map(Exp::Time),
+            "a time like '09:13:00'",
+        ),
+        Function::Timestamp => (
+            parse_time(text).map(Exp::Timestamp),
+            "a timestamp like '2026-10-18T09:13:00Z'",
+        ),
+        Function::Interval => (
+            parse_interval(text).map(Exp::Interval),
+            "an interval like '2 days 3 hours'",
+        ),
+        _ => return None,
+    };
+    Some(value.ok_or_else(|| Error::Parse(format!("expected {}, found '{}'", example, text))))
+}
+
+/// The function and text that [`convert`] reads back as the value.
+pub(crate) fn format(exp: &Exp) -> Option<(Function, String)> {
+    match exp {
+        Exp::Date(date) => Some((Function::Date, date.format("%Y-%m-%d").to_string())),
+        Exp::Time(time) => Some((Function::Time, time.to_string())),
+        Exp::Timestamp(time) => Some((
+            Function::Timestamp,
+            time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        )),
+        Exp::Interval(interval) => Some((Function::Interval, format_interval(*interval))),
+        _ => None,
+    }
+}
+
+/// Read a sum of amounts like `1 week -2 days 3.5 seconds`. Only seconds can
+/// have a fraction.
+fn parse_interval(text: &str) -> Option<TimeDelta> {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    if words.is_empty() || words.len() % 2 != 0 {
+        return None;
+    }
+    let mut total = TimeDelta::zero();
+    for pair in words.chunks(2) {
+        let unit = match pair[1].strip_suffix('s').unwrap_or(pair[1]) {
+            "week" => 7 * DAY,
+            "day" => DAY,
+            "hour" => 60 * 60,
+            "minute" => 60,
+            "second" => 1,
+            _ => return None,
+        };
+        let (whole, fraction) = pair[0].split_once('.').unwrap_or((pair[0], ""));
+        let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        let whole_digits = whole.strip_prefix('-').unwrap_or(whole);
+        if whole_digits.is_empty() || !digits(whole_digits) || !digits(fraction) {
+            return None;
+        }
+        if fraction.len() > 9 || (unit != 1 && !fraction.is_empty()) {
+            return None;
+        }
+        let secs = whole.parse::<i64>().ok()?.checked_mul(unit)?;
+        let nanos = format!("{:0<9}", fraction).parse::<i64>().ok()?;
+        let nanos = if whole.starts_with('-') {
+            -nanos
+        } else {
+            nanos
+        };
+        let amount = TimeDelta::new(secs, 0)?.checked_add(&TimeDelta::nanoseconds(nanos))?;
+        total = total.checked_add(&amount)?;
+    }
+    Some(total)
+}
+
+/// Write an interval as days, hours, minutes and seconds, each with the
+/// interval's sign.
+fn format_interval(interval: TimeDelta) -> String {
+    let sign = if interval < TimeDelta::zero() {
+        "-"
+    } else {
+        ""
+    };
+    let secs = interval.num_seconds().unsigned_abs();
+    let nanos = interval.subsec_nanos().unsigned_abs();
+    let plural = |n: u64| if n == 1 { "" } else { "s" };
+    let mut parts = vec![];
+    for (n, unit) in [
+        (secs / DAY as u64, "day"),
+        (secs % DAY as u64 / 3600, "hour"),
+        (secs % 3600 / 60, "minute"),
+    ] {
+        if n > 0 {
+            parts.push(format!("{}{} {}{}", sign, n, unit, plural(n)));
+        }
+    }
+    let seconds = secs % 60;
+    if seconds > 0 || nanos > 0 || parts.is_empty() {
+        let fraction = match nanos {
+            0 => String::new(),
+            _ => format!(".{:09}", nanos).trim_end_matches('0').to_string(),
+        };
+        let plural = if nanos == 0 { plural(seconds) } else { "s" };
+        parts.push(format!("{}{}{} second{}", sign, seconds, fraction, plural));
+    }
+    parts.join(" ")
+}
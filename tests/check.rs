@@ -0,0 +1,220 @@
+use sdb::{check, parse_spanned, read_eval, type_env, Env, Error, Span, Type, TypeEnv};
+
+macro_rules! ok {
+    ($input:expr, $output:expr) => {{
+        let ty = check(&parse_spanned($input).unwrap(), &TypeEnv::new()).unwrap();
+        assert_eq!(ty.to_string(), $output);
+    }};
+}
+
+macro_rules! fail {
+    ($input:expr, $error:expr) => {{
+        let error = check(&parse_spanned($input).unwrap(), &TypeEnv::new()).unwrap_err();
+        assert_eq!(error, $error);
+    }};
+}
+
+fn mismatch(start: usize, end: usize, expected: &str, found: &str) -> Error {
+    Error::At(
+        Span { start, end },
+        Box::new(Error::TypeMismatch {
+            expected: expected.to_string(),
+            found: found.to_string(),
+        }),
+    )
+}
+
+#[test]
+fn test_values() {
+    ok!("1", "integer");
+    ok!("'a' == 'b'", "boolean");
+    ok!("x = 'a'; x", "string");
+    ok!("1 + 2 * 3", "integer");
+    ok!("1 + 2.5d", "decimal");
+    ok!("1.5d / 2.0", "float");
+    ok!("nil # sum x <- x decimal : 1d", "table (sum_x decimal)");
+    ok!("2 < 1.5 && 1d == 1", "boolean");
+    ok!("id, name : 1, 'Alice'", "table (id integer, name string)");
+    ok!(
+        "id, name : 1, 'Alice', 'two', 'Bob'",
+        "table (id, name string)"
+    );
+    ok!("id : 1 - id : 1", "table (id integer)");
+    ok!("id int : nil", "table (id integer)");
+    ok!("null", "expression");
+    ok!("concat 'a' (upper 'b')", "string");
+    ok!("length 'abc' > 2 && 'abc' like 'a%'", "boolean");
+    ok!("null is null && null", "boolean");
+    ok!(
+        "id, name : 1, null, null, 'Bob'",
+        "table (id integer, name string)"
+    );
+    ok!("date '2026-10-18' - interval '1 day'", "date");
+    ok!("now - timestamp '2026-01-01'", "interval");
+    ok!("2 * interval '1 hour' + time '09:00'", "time");
+    ok!(
+        "hired : '2026-10-18' ? timestamp hired < now",
+        "table (hired string)"
+    );
+    ok!(
+        "x = 'a'; id any, name str : 1, x",
+        "table (id integer, name string)"
+    );
+    ok!(
+        "schema (id : 1)",
+        "table (column string, type string, key boolean, unique boolean, references string)"
+    );
+}
+
+#[test]
+fn test_tables() {
+    let staff = "Staff = id, name, salary : 1, 'Alice', 10, 2, 'Bob', 20;\n";
+    let ok = |body: &str, output: &str| ok!(&format!("{}{}", staff, body), output);
+
+    ok("name <- Staff", "table (name string)");
+    ok(
+        "Staff ? salary > 15 && not (name == 'Alice')",
+        "table (id integer, name string, salary integer)",
+    );
+    ok(
+        "id -> key <~ Staff",
+        "table (key integer, name string, salary integer)",
+    );
+    ok(
+        "double := (salary * 2) <+ Staff",
+        "table (id integer, name string, salary integer, double integer)",
+    );
+    ok(
+        "name # count, sum salary, max name <- Staff",
+        "table (name string, count integer, sum_salary integer, max_name string)",
+    );
+    ok(
+        "Staff >< id, team : 1, 'red'",
+        "table (id integer, name string, salary integer, team string)",
+    );
+    ok("left.id <- Staff * id : 3", "table (left.id integer)");
+    ok(
+        "Staff >< (id : 1) on left.id == right.id",
+        "table (left.id integer, name string, salary integer, right.id integer)",
+    );
+}
+
+#[test]
+fn test_errors() {
+    fail!("a : 1 ? a", mismatch(8, 9, "boolean", "integer"));
+    fail!("a : 1 ? a + 1", mismatch(8, 13, "boolean", "integer"));
+    fail!("a <- 1", mismatch(5, 6, "table", "integer"));
+    fail!("(a : 1) + 'x'", mismatch(10, 13, "table", "string"));
+    fail!("a : 1 ? a < 'x'", mismatch(12, 15, "integer", "string"));
+    fail!("not 1", mismatch(4, 5, "boolean", "integer"));
+    fail!("concat 'a' 1", mismatch(11, 12, "string", "integer"));
+    fail!("1 like 'a'", mismatch(0, 1, "string", "integer"));
+    fail!(
+        "date '2026-10-18' + 1",
+        mismatch(20, 21, "interval", "integer")
+    );
+    fail!(
+        "interval '1 day' * 1.5",
+        mismatch(19, 22, "integer", "float")
+    );
+    fail!(
+        "'x' - interval '1 day'",
+        mismatch(0, 3, "table or number", "string")
+    );
+    fail!("date 1", mismatch(5, 6, "string", "integer"));
+    fail!(
+        "x = 'a'; id int : x",
+        mismatch(18, 19, "integer in column `id`", "string")
+    );
+    fail!(
+        "b <- a : 1",
+        Error::At(
+            Span { start: 0, end: 10 },
+            Box::new(Error::UnknownColumn("b".to_string()))
+        )
+    );
+    fail!(
+        "a : 1 ? b == 1",
+        Error::At(
+            Span { start: 8, end: 9 },
+            Box::new(Error::UnknownVariable("b".to_string()))
+        )
+    );
+    fail!(
+        "a : 1 - b : 2",
+        Error::At(
+            Span { start: 0, end: 13 },
+            Box::new(Error::ColumnMismatch {
+                op: "difference".to_string(),
+                left: vec!["a".to_string()],
+                right: vec!["b".to_string()],
+            })
+        )
+    );
+    fail!(
+        "nil # sum a <- a : 'x'",
+        Error::At(
+            Span { start: 0, end: 22 },
+            Box::new(Error::TypeMismatch {
+                expected: "number".to_string(),
+                found: "string".to_string(),
+            })
+        )
+    );
+}
+
+#[test]
+fn test_functions() {
+    ok!("f x = x + 1; f", "function");
+    ok!("f x = x + 1; f 2", "integer");
+    ok!("f x = x + 1; f 2.5", "float");
+    ok!("t = a : 1; f d = t ? a == d; f 1", "table (a integer)");
+    ok!("n = 'a'; f x = concat x n; n = 1; f 'b'", "string");
+
+    // The body is checked again with the types of the arguments
+    fail!(
+        "f x = x + 1; f 'a'",
+        mismatch(6, 7, "table or number", "string")
+    );
+    fail!("x = 1; x 2", mismatch(7, 8, "function", "integer"));
+    fail!(
+        "f x = x; f 1 2",
+        Error::At(
+            Span { start: 9, end: 14 },
+            Box::new(Error::Arity {
+                function: "f".to_string(),
+                expected: 1,
+                found: 2,
+            })
+        )
+    );
+    fail!(
+        "foo 1",
+        Error::At(
+            Span { start: 0, end: 5 },
+            Box::new(Error::UnknownFunction("foo".to_string()))
+        )
+    );
+}
+
+#[test]
+fn test_unevaluated() {
+    // Evaluation never looks at the condition of an empty table, but the
+    // checker still finds the mistake
+    let text = "t = (a : 1) - (a : 1); t ? a";
+    let (_, env) = read_eval("t = (a : 1) - (a : 1); nil", &Env::new()).unwrap();
+    assert!(read_eval("t ? a", &env).is_ok());
+    assert!(check(&parse_spanned(text).unwrap(), &TypeEnv::new()).is_err());
+
+    // Values bound outside the program are typed from their contents
+    let (_, env) = read_eval("t = a : 1; nil", &Env::new()).unwrap();
+    let exp = parse_spanned("t ? a").unwrap();
+    assert_eq!(
+        check(&exp, &type_env(&env)),
+        Err(mismatch(4, 5, "boolean", "integer"))
+    );
+    assert_eq!(
+        Type::of(env.get("t").unwrap()),
+        Type::Table(vec![("a".to_string(), Type::Int)])
+    );
+}
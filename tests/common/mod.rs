@@ -0,0 +1,45 @@
+// Each test crate uses its own subset of these
+#![allow(dead_code)]
+
+use sdb::{execute, Error, Exp, Server, Store};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// An empty directory for one test to keep its store in.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sdb-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+pub fn conf(dir: &Path) -> Server {
+    Server {
+        directory: dir.to_string_lossy().into_owned(),
+        port: 0,
+        verbose: false,
+        bag: false,
+    }
+}
+
+/// Open the store in `dir`, and return it along with its configuration and
+/// a closure that runs a program against it the way the server does.
+pub async fn open(
+    dir: &Path,
+) -> (
+    Arc<Server>,
+    Arc<Store>,
+    impl AsyncFn(&str) -> Result<Exp, Error>,
+) {
+    let conf = Arc::new(conf(dir));
+    let store = Arc::new(Store::new(dir));
+    store.open().await.unwrap();
+    let run = {
+        let (conf, store) = (Arc::clone(&conf), Arc::clone(&store));
+        async move |text: &str| execute(text, &conf, &store).await
+    };
+    (conf, store, run)
+}
@@ -1,4 +1,17 @@
-use sdb::{read_eval, Env, Exp::*};
+use sdb::{
+    parse, read_eval, read_eval_with, Env, Error, Exp, Exp::*, Relation, Semantics, Span, F64,
+};
+
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+fn decimal(text: &str) -> Exp {
+    Decimal(Decimal::from_str(text).unwrap())
+}
+
+fn table(vars: Vec<String>, cells: Vec<Exp>) -> Exp {
+    Table(Relation::from_cells(vars, cells).unwrap())
+}
 
 macro_rules! run {
     ($input:expr, $output:expr) => {{
@@ -7,11 +20,18 @@ macro_rules! run {
     }};
 }
 
+macro_rules! fail {
+    ($input:expr, $error:expr) => {{
+        let error = read_eval($input, &Env::new()).unwrap_err();
+        assert_eq!(error, $error);
+    }};
+}
+
 #[test]
 fn test_select() {
     run!(
         "name <- name, id : 'Alice', 1, 'Bob', 2",
-        Table(
+        table(
             vec!["name".to_string()],
             vec![Str("Alice".to_string()), Str("Bob".to_string())]
         )
@@ -19,12 +39,17 @@ fn test_select() {
 
     run!(
         "id <- name, id : 'Alice', 1, 'Bob', 2",
-        Table(vec!["id".to_string()], vec![Int(1), Int(2)])
+        table(vec!["id".to_string()], vec![Int(1), Int(2)])
     );
 
-    run!(
+    // Selecting a missing column used to give an empty table with that
+    // column; now that tables are validated it's an error
+    fail!(
         "foo <- name, id : 'Alice', 1, 'Bob', 2",
-        Table(vec!["foo".to_string()], vec![])
+        Error::At(
+            Span { start: 0, end: 38 },
+            Box::new(Error::UnknownColumn("foo".to_string()))
+        )
     );
 }
 
@@ -32,7 +57,7 @@ fn test_select() {
 fn test_where() {
     run!(
         "name, id : 'Alice', 1, 'Bob', 2 ? name == 'Alice'",
-        Table(
+        table(
             vec!["name".to_string(), "id".to_string()],
             vec![Str("Alice".to_string()), Int(1)]
         )
@@ -40,7 +65,7 @@ fn test_where() {
 
     run!(
         "name, id : 'Alice', 1, 'Bob', 2 ? id == 2",
-        Table(
+        table(
             vec!["name".to_string(), "id".to_string()],
             vec![Str("Bob".to_string()), Int(2)]
         )
@@ -48,7 +73,7 @@ fn test_where() {
 
     run!(
         "name, id : 'Alice', 1, 'Bob', 2 ? id == 1 || id == 2",
-        Table(
+        table(
             vec!["name".to_string(), "id".to_string()],
             vec![
                 Str("Alice".to_string()),
@@ -61,7 +86,7 @@ fn test_where() {
 
     run!(
         "name, id : 'Alice', 1, 'Bob', 2 ? name == 'Foo'",
-        Table(vec!["name".to_string(), "id".to_string()], vec![])
+        table(vec!["name".to_string(), "id".to_string()], vec![])
     );
 }
 
@@ -69,7 +94,7 @@ fn test_where() {
 fn test_union() {
     run!(
         "name, id : 'Alice', 1 + name, id : 'Bob', 2",
-        Table(
+        table(
             vec!["name".to_string(), "id".to_string()],
             vec![
                 Str("Alice".to_string()),
@@ -82,18 +107,51 @@ fn test_union() {
 
     run!(
         "table = name, id : 'Alice', 1; table + table",
-        Table(
+        table(
             vec!["name".to_string(), "id".to_string()],
-            vec![
-                Str("Alice".to_string()),
-                Int(1),
-                Str("Alice".to_string()),
-                Int(1),
-            ]
+            vec![Str("Alice".to_string()), Int(1)]
         )
     );
 }
 
+#[test]
+fn test_sets() {
+    run!(
+        "a : 1, 2, 1",
+        table(vec!["a".to_string()], vec![Int(1), Int(2)])
+    );
+
+    run!(
+        "a <- a, b : 1, 2, 1, 3",
+        table(vec!["a".to_string()], vec![Int(1)])
+    );
+
+    run!(
+        "(a <- a, b : 1, 2, 1, 3) * (c <- c, d : 4, 5, 4, 6)",
+        table(vec!["a".to_string(), "c".to_string()], vec![Int(1), Int(4)])
+    );
+}
+
+#[test]
+fn test_bags() {
+    let bag = |text| read_eval_with(text, &Env::new(), Semantics::Bag).unwrap().0;
+
+    assert_eq!(
+        bag("a : 1, 1 + a : 1"),
+        table(vec!["a".to_string()], vec![Int(1), Int(1), Int(1)])
+    );
+
+    assert_eq!(
+        bag("a <- a, b : 1, 2, 1, 3"),
+        table(vec!["a".to_string()], vec![Int(1), Int(1)])
+    );
+
+    assert_eq!(
+        bag("a : 1, 1, 1, 2 - a : 1, 2"),
+        table(vec!["a".to_string()], vec![Int(1), Int(1)])
+    );
+}
+
 #[test]
 fn test_product() {
     run!(
@@ -108,7 +166,7 @@ Sizes = size : 'Small', 'Medium', 'Large';
 
 Colors * Sizes
 "#,
-        Table(
+        table(
             vec!["color".to_string(), "hex".to_string(), "size".to_string()],
             vec![
                 Str("Red".to_string()),
@@ -145,6 +203,16 @@ Colors * Sizes
 
 #[test]
 fn test_difference() {
+    // `+` binds more loosely than `-`
+    run!(
+        "A = a : 1; B = a : nil; C = a : 1; A + B - C",
+        table(vec!["a".to_string()], vec![Int(1)])
+    );
+    run!(
+        "A = a : 1; B = a : nil; C = a : 1; (A + B) - C",
+        table(vec!["a".to_string()], vec![])
+    );
+
     run!(
         r#"
 Left =
@@ -158,7 +226,7 @@ Right =
 
 Left - Right
 "#,
-        Table(vec!["a".to_string(), "b".to_string()], vec![Int(3), Int(4)])
+        table(vec!["a".to_string(), "b".to_string()], vec![Int(3), Int(4)])
     );
 
     run!(
@@ -174,9 +242,844 @@ Right =
 
 Left - Right
 "#,
-        Table(
+        table(
             vec!["a".to_string(), "b".to_string()],
             vec![Int(1), Int(2), Int(3), Int(4)],
         )
     );
 }
+
+#[test]
+fn test_arithmetic() {
+    run!("1 + 2 * 3", Int(7));
+    run!("(1 + 2) * 3", Int(9));
+    run!("1 - 2 - 3", Int(-4));
+    run!("1 - 2 + 3", Int(2));
+    run!("7 / 2", Int(3));
+    run!("-7 / 2", Int(-3));
+
+    fail!(
+        "9223372036854775807 + 1",
+        Error::At(
+            Span { start: 0, end: 23 },
+            Box::new(Error::Arithmetic("integer overflow".to_string()))
+        )
+    );
+
+    fail!(
+        "1 / (1 - 1)",
+        Error::At(
+            Span { start: 0, end: 11 },
+            Box::new(Error::Arithmetic("division by zero".to_string()))
+        )
+    );
+
+    fail!(
+        "1 + a : 1",
+        Error::At(
+            Span { start: 4, end: 9 },
+            Box::new(Error::TypeMismatch {
+                expected: "number".to_string(),
+                found: "table".to_string(),
+            })
+        )
+    );
+}
+
+#[test]
+fn test_numbers() {
+    run!("1.5 + 2.25", Float(F64(3.75)));
+    run!("7.0 / 2", Float(F64(3.5)));
+    run!("0.1d + 0.2d", decimal("0.3"));
+    run!("1.50d * 2", decimal("3.00"));
+    run!("1d / 3d", decimal("0.3333333333333333333333333333"));
+    run!("0.5d + 0.25", Float(F64(0.75)));
+    run!("1.5e3 - 500", Float(F64(1000.0)));
+
+    run!("1 == 1.0", Bool(true));
+    run!("1.50d == 1.5d", Bool(true));
+    run!("2 < 2.5", Bool(true));
+    run!("(0.1d + 0.2d) == 0.3d", Bool(true));
+    run!("-0.5 < 0.25d", Bool(true));
+
+    run!(
+        "nil # sum x, avg x, max x <- x : 1.5, 2.5, 4",
+        parse("sum_x, avg_x, max_x : 8.0, 2.6666666666666665, 4").unwrap()
+    );
+    run!(
+        "nil # sum x, avg x <- x : 1.10d, 2.20d",
+        parse("sum_x, avg_x : 3.30d, 1.65d").unwrap()
+    );
+    run!(
+        "x <- x : 3, 1.5, 2.5d ? x > 2",
+        parse("x : 3, 2.5d").unwrap()
+    );
+    run!("x float : 1.5", parse("x float : 1.5").unwrap());
+
+    // Rows are told apart, matched and grouped with the same equality as `==`
+    run!("a : 1, 1.0, 1.00d", parse("a : 1").unwrap());
+    run!("(a : 1) + (a : 1.0)", parse("a : 1").unwrap());
+    run!("(a : 1, 2) - (a : 1.0, 2d)", parse("a : nil").unwrap());
+    run!(
+        "(a, b : 1, 'x') >< (a, c : 1.0, 'y', 1d, 'z')",
+        parse("a, b, c : 1, 'x', 'y', 1, 'x', 'z'").unwrap()
+    );
+    run!(
+        "a # count <- a, b : 1, 'x', 1.0, 'y', 1d, 'z', 2, 'x'",
+        parse("a, count : 1, 3, 2, 1").unwrap()
+    );
+    // Including -0.0, which equals 0.0
+    run!("0.0 == -0.0", Bool(true));
+    run!("x = 0.0 * -1.0; x == 0", Bool(true));
+    run!("-0.0 < 0.0 || -0.0 > 0", Bool(false));
+    run!("a : 0.0, -0.0, 0", parse("a : 0.0").unwrap());
+    run!("(a : 0, 1) - (a : -0.0)", parse("a : 1").unwrap());
+    run!(
+        "a # count <- a, b : -0.0, 1, 0.0d, 2",
+        parse("a, count : -0.0, 2").unwrap()
+    );
+    assert_eq!(
+        read_eval("a key, b : 1, 'x', 1.0, 'y'", &Env::new())
+            .unwrap_err()
+            .to_string(),
+        "more than one row with `a` = 1.0"
+    );
+
+    fail!(
+        "1e308 * 10",
+        Error::At(
+            Span { start: 0, end: 10 },
+            Box::new(Error::Arithmetic("float overflow".to_string()))
+        )
+    );
+    fail!(
+        "1.5d / 0",
+        Error::At(
+            Span { start: 0, end: 8 },
+            Box::new(Error::Arithmetic("division by zero".to_string()))
+        )
+    );
+    fail!(
+        "x float : 1",
+        Error::At(
+            Span { start: 0, end: 11 },
+            Box::new(Error::TypeMismatch {
+                expected: "float in column `x`".to_string(),
+                found: "integer".to_string(),
+            })
+        )
+    );
+}
+
+#[test]
+fn test_times() {
+    let exp = |text: &str| parse(text).unwrap();
+    run!(
+        "date '2026-10-18' + interval '2 weeks'",
+        exp("date '2026-11-01'")
+    );
+    run!(
+        "interval '1 day' + date '2026-12-31'",
+        exp("date '2027-01-01'")
+    );
+    run!(
+        "date '2027-03-01' - date '2026-03-01'",
+        exp("interval '365 days'")
+    );
+    run!("time '23:30' + interval '45 minutes'", exp("time '00:15'"));
+    run!(
+        "time '09:00' - time '17:30'",
+        exp("interval '-8 hours -30 minutes'")
+    );
+    run!(
+        "timestamp '2026-10-18T09:13:00Z' - interval '1 hour 0.5 seconds'",
+        exp("timestamp '2026-10-18T08:12:59.5Z'")
+    );
+    run!(
+        "3 * interval '1 hour' - interval '20 minutes'",
+        exp("interval '2 hours 40 minutes'")
+    );
+    run!("date '2026-10-18' + null", Null);
+
+    run!("date '2026-10-18' < date '2026-10-19'", Bool(true));
+    run!("interval '1 day' == interval '24 hours'", Bool(true));
+    run!(
+        "timestamp '2026-10-18T09:13:00Z' == timestamp '2026-10-18T11:13:00+02:00'",
+        Bool(true)
+    );
+    run!("now > timestamp '2026-01-01' && now() <= now", Bool(true));
+
+    // Hire dates kept as strings can be converted to compare them
+    run!(
+        "Staff = name, hired : 'Alice', '2019-04-01', 'Bob', '2024-09-15'; name <- Staff ? date hired < date '2020-01-01'",
+        exp("name : 'Alice'")
+    );
+    run!(
+        "nil # min hired, max hired <- hired date : (date '2024-09-15'), (date '2019-04-01')",
+        exp("min_hired, max_hired : date '2019-04-01', date '2024-09-15'")
+    );
+
+    fail!(
+        "date '2026-10-18' + interval '36 hours'",
+        Error::At(
+            Span { start: 0, end: 39 },
+            Box::new(Error::Arithmetic(
+                "can't add part of a day to a date".to_string()
+            ))
+        )
+    );
+    fail!(
+        "date '9999-12-31' + interval '1 day'",
+        Error::At(
+            Span { start: 0, end: 36 },
+            Box::new(Error::Arithmetic("date or time out of range".to_string()))
+        )
+    );
+    fail!(
+        "date '2026-10-18' - time '09:00'",
+        Error::At(
+            Span { start: 20, end: 32 },
+            Box::new(Error::TypeMismatch {
+                expected: "interval or date".to_string(),
+                found: "time".to_string(),
+            })
+        )
+    );
+    fail!(
+        "hired : '2026-02-30' ? date hired > date '2026-01-01'",
+        Error::At(
+            Span { start: 23, end: 33 },
+            Box::new(Error::Parse(
+                "expected a date like '2026-10-18', found '2026-02-30'".to_string()
+            ))
+        )
+    );
+}
+
+#[test]
+fn test_compare() {
+    run!("1 < 2", Bool(true));
+    run!("2 <= 2", Bool(true));
+    run!("1 > 2", Bool(false));
+    run!("'b' >= 'a'", Bool(true));
+    run!("false < true", Bool(true));
+    run!("1 == 1 && 2 == 2", Bool(true));
+    run!("(1 + 1) > 1", Bool(true));
+    run!("1 + 1 > 1", Bool(true));
+    run!("2 * 3 - 1 <= 5", Bool(true));
+    run!("x = 4; x > 3 && true", Bool(true));
+    run!("x = 4; x > 3 && x < 4 || x + 1 == 5", Bool(true));
+    run!(
+        "Staff = age : 25, 40, 60; Staff ? age > 30 && age < 50",
+        table(vec!["age".to_string()], vec![Int(40)])
+    );
+    run!(
+        "Staff = age : 25, 40; Staff ? age + 1 == 41",
+        table(vec!["age".to_string()], vec![Int(40)])
+    );
+
+    run!(
+        "name, age : 'Alice', 31, 'Bob', 25 ? age > 30",
+        table(
+            vec!["name".to_string(), "age".to_string()],
+            vec![Str("Alice".to_string()), Int(31)]
+        )
+    );
+
+    fail!(
+        "1 < 'a'",
+        Error::At(
+            Span { start: 4, end: 7 },
+            Box::new(Error::TypeMismatch {
+                expected: "integer".to_string(),
+                found: "string".to_string(),
+            })
+        )
+    );
+}
+
+#[test]
+fn test_join() {
+    let tables = r#"
+Staff = id, name, dept : 1, 'Alice', 10, 2, 'Bob', 20, 3, 'Carol', 10;
+Dept = dept, name : 10, 'Eng', 30, 'Ops';
+"#;
+
+    run!(
+        &format!("{} Staff >< Dept", tables),
+        table(
+            vec!["id".to_string(), "name".to_string(), "dept".to_string()],
+            vec![]
+        )
+    );
+
+    run!(
+        &format!("{} Staff >< (dept <- Dept)", tables),
+        table(
+            vec!["id".to_string(), "name".to_string(), "dept".to_string()],
+            vec![
+                Int(1),
+                Str("Alice".to_string()),
+                Int(10),
+                Int(3),
+                Str("Carol".to_string()),
+                Int(10)
+            ]
+        )
+    );
+
+    run!(
+        &format!(
+            "{} Staff.name, Dept.name <- Staff >< Dept on (Staff.dept == Dept.dept) && not (id == 1)",
+            tables
+        ),
+        table(
+            vec!["Staff.name".to_string(), "Dept.name".to_string()],
+            vec![Str("Carol".to_string()), Str("Eng".to_string())]
+        )
+    );
+
+    run!(
+        &format!(
+            "{} left.name, right.name <- Staff >< Staff on (left.id == right.id) && (right.dept == 20)",
+            tables
+        ),
+        table(
+            vec!["left.name".to_string(), "right.name".to_string()],
+            vec![Str("Bob".to_string()), Str("Bob".to_string())]
+        )
+    );
+
+    run!(
+        &format!(
+            "{} id, Dept.dept <- Staff * Dept ? Staff.dept == Dept.dept",
+            tables
+        ),
+        table(
+            vec!["id".to_string(), "Dept.dept".to_string()],
+            vec![Int(1), Int(10), Int(3), Int(10)]
+        )
+    );
+}
+
+#[test]
+fn test_rename() {
+    run!(
+        "a -> b, b -> a <~ a, b, c : 1, 2, 3",
+        table(
+            vec!["b".to_string(), "a".to_string(), "c".to_string()],
+            vec![Int(1), Int(2), Int(3)]
+        )
+    );
+
+    run!(
+        "Names = name : 'Ann', 'Ben'; left.name -> a, right.name -> b <~ Names * Names",
+        table(
+            vec!["a".to_string(), "b".to_string()],
+            vec![
+                Str("Ann".to_string()),
+                Str("Ann".to_string()),
+                Str("Ann".to_string()),
+                Str("Ben".to_string()),
+                Str("Ben".to_string()),
+                Str("Ann".to_string()),
+                Str("Ben".to_string()),
+                Str("Ben".to_string())
+            ]
+        )
+    );
+
+    run!(
+        "(x -> a <~ x : 1) + a : 2",
+        table(vec!["a".to_string()], vec![Int(1), Int(2)])
+    );
+
+    fail!(
+        "c -> d <~ a, b : 1, 2",
+        Error::At(
+            Span { start: 0, end: 21 },
+            Box::new(Error::UnknownColumn("c".to_string()))
+        )
+    );
+
+    fail!(
+        "a -> b <~ a, b : 1, 2",
+        Error::At(
+            Span { start: 0, end: 21 },
+            Box::new(Error::DuplicateColumn("b".to_string()))
+        )
+    );
+}
+
+#[test]
+fn test_extend() {
+    run!(
+        "is_admin := name == 'root' || id == 0, active := true <+ id, name : 0, 'root', 7, 'ann'",
+        table(
+            vec![
+                "id".to_string(),
+                "name".to_string(),
+                "is_admin".to_string(),
+                "active".to_string()
+            ],
+            vec![
+                Int(0),
+                Str("root".to_string()),
+                Bool(true),
+                Bool(true),
+                Int(7),
+                Str("ann".to_string()),
+                Bool(false),
+                Bool(true)
+            ]
+        )
+    );
+
+    run!(
+        "b := a, c := not b <+ a : true",
+        table(
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vec![Bool(true), Bool(true), Bool(false)]
+        )
+    );
+
+    fail!(
+        "a := 1 <+ a : 2",
+        Error::At(
+            Span { start: 0, end: 15 },
+            Box::new(Error::DuplicateColumn("a".to_string()))
+        )
+    );
+
+    fail!(
+        "b := c <+ a : 2",
+        Error::At(
+            Span { start: 5, end: 6 },
+            Box::new(Error::UnknownVariable("c".to_string()))
+        )
+    );
+}
+
+#[test]
+fn test_group() {
+    let staff = r#"
+Staff =
+  name, dept, salary :
+  'Alice', 'eng', 100,
+  'Bob', 'ops', 80,
+  'Carol', 'eng', 121;
+"#;
+
+    run!(
+        &format!("{} dept # count, sum salary, avg salary <- Staff", staff),
+        table(
+            vec![
+                "dept".to_string(),
+                "count".to_string(),
+                "sum_salary".to_string(),
+                "avg_salary".to_string()
+            ],
+            vec![
+                Str("eng".to_string()),
+                Int(2),
+                Int(221),
+                Int(110),
+                Str("ops".to_string()),
+                Int(1),
+                Int(80),
+                Int(80)
+            ]
+        )
+    );
+
+    run!(
+        &format!("{} nil # count, min name, max salary <- Staff", staff),
+        table(
+            vec![
+                "count".to_string(),
+                "min_name".to_string(),
+                "max_salary".to_string()
+            ],
+            vec![Int(3), Str("Alice".to_string()), Int(121)]
+        )
+    );
+
+    run!(
+        "nil # count, sum a <- a : nil",
+        table(
+            vec!["count".to_string(), "sum_a".to_string()],
+            vec![Int(0), Int(0)]
+        )
+    );
+
+    run!(
+        "a # count <- a : nil",
+        table(vec!["a".to_string(), "count".to_string()], vec![])
+    );
+
+    // There is no least, greatest or average value of nothing
+    run!(
+        "nil # max a <- a : nil",
+        table(vec!["max_a".to_string()], vec![Null])
+    );
+    run!(
+        "d # min s, max s, avg s, sum s <- d, s : 'a', null, 'b', 1, 'a', null",
+        table(
+            vec![
+                "d".to_string(),
+                "min_s".to_string(),
+                "max_s".to_string(),
+                "avg_s".to_string(),
+                "sum_s".to_string()
+            ],
+            vec![
+                Str("a".to_string()),
+                Null,
+                Null,
+                Null,
+                Int(0),
+                Str("b".to_string()),
+                Int(1),
+                Int(1),
+                Int(1),
+                Int(1)
+            ]
+        )
+    );
+
+    fail!(
+        "b # count <- a : 1",
+        Error::At(
+            Span { start: 0, end: 18 },
+            Box::new(Error::UnknownColumn("b".to_string()))
+        )
+    );
+
+    fail!(
+        "nil # sum a <- a : 'x'",
+        Error::At(
+            Span { start: 0, end: 22 },
+            Box::new(Error::TypeMismatch {
+                expected: "number".to_string(),
+                found: "string".to_string(),
+            })
+        )
+    );
+}
+
+#[test]
+fn test_types() {
+    let staff = "Staff = id int, name str : 1, 'Alice', 2, 'Bob';";
+    let typed = |body: &str| format!("{} {}", staff, body);
+
+    // Types are kept by operators that don't change a column's values
+    run!(
+        &typed("Staff ? id == 1"),
+        parse("id int, name str : 1, 'Alice'").unwrap()
+    );
+    run!(
+        &typed("name <- Staff"),
+        parse("name str : 'Alice', 'Bob'").unwrap()
+    );
+    run!(
+        &typed("Staff + id, name : 3, 'Carol'"),
+        parse("id int, name str : 1, 'Alice', 2, 'Bob', 3, 'Carol'").unwrap()
+    );
+    run!(
+        &typed("id -> key <~ Staff - id, name : 2, 'Bob'"),
+        parse("key int, name str : 1, 'Alice'").unwrap()
+    );
+    run!(
+        &typed("column, type <- schema (x := 1 <+ Staff)"),
+        parse("column str, type str : 'id', 'int', 'name', 'str', 'x', 'any'").unwrap()
+    );
+
+    fail!(
+        &typed("Staff + id, name : 'three', 3"),
+        Error::At(
+            Span { start: 49, end: 78 },
+            Box::new(Error::TypeMismatch {
+                expected: "integer in column `id`".to_string(),
+                found: "string".to_string(),
+            })
+        )
+    );
+    fail!(
+        "x = 'one'; id int : x",
+        Error::At(
+            Span { start: 11, end: 21 },
+            Box::new(Error::TypeMismatch {
+                expected: "integer in column `id`".to_string(),
+                found: "string".to_string(),
+            })
+        )
+    );
+}
+
+#[test]
+fn test_keys() {
+    let staff = "Staff = id int key, email unique, name : 1, 'a@x', 'Alice', 2, 'b@x', 'Bob';";
+    let keyed = |body: &str| format!("{} {}", staff, body);
+
+    run!(
+        &keyed("Staff ? id == 2"),
+        parse("id int key, email unique, name : 2, 'b@x', 'Bob'").unwrap()
+    );
+    run!(
+        "a key, b key : 1, 1, 1, 2",
+        parse("a key, b key : 1, 1, 1, 2").unwrap()
+    );
+    // Projecting away part of a key keeps the rows, but not the key
+    run!("a <- a key, b key : 1, 1, 1, 2", parse("a : 1").unwrap());
+    run!(
+        "key, unique <- schema (a int key, b unique : 1, 2)",
+        parse("key bool, unique bool : true, false, false, true").unwrap()
+    );
+
+    let duplicate = |key: Vec<(&str, Exp)>| {
+        Error::DuplicateKey(
+            key.into_iter()
+                .map(|(var, exp)| (var.to_string(), exp))
+                .collect(),
+        )
+    };
+    let error = read_eval(
+        &keyed("Staff + id, email, name : 1, 'c@x', 'Carol'"),
+        &Env::new(),
+    )
+    .unwrap_err();
+    assert_eq!(error.to_string(), "more than one row with `id` = 1");
+    assert_eq!(error.category(), "constraint");
+    fail!(
+        &keyed("Staff + id, email, name : 3, 'a@x', 'Carol'"),
+        Error::At(
+            Span {
+                start: 77,
+                end: 120
+            },
+            Box::new(duplicate(vec![("email", Str("a@x".to_string()))]))
+        )
+    );
+    fail!(
+        "a key, b key, c : 1, 2, 3 + a, b, c : 1, 2, 4",
+        Error::At(
+            Span { start: 0, end: 45 },
+            Box::new(duplicate(vec![("a", Int(1)), ("b", Int(2))]))
+        )
+    );
+
+    // Repeated rows only break a key when tables are bags
+    run!("a key : 1, 1", parse("a key : 1").unwrap());
+    assert!(read_eval_with("a key : 1, 1", &Env::new(), Semantics::Bag).is_err());
+}
+
+#[test]
+fn test_null() {
+    run!("null", Null);
+    run!("null == null", Null);
+    run!("1 < null", Null);
+    run!("1 + null", Null);
+    run!("null / 2", Null);
+    run!("not null", Null);
+    run!("false && null", Bool(false));
+    run!("null && true", Null);
+    run!("true || null", Bool(true));
+    run!("null || false", Null);
+    run!("null is null", Bool(true));
+    run!("1 is not null", Bool(true));
+
+    let staff = "Staff = id, manager : 1, null, 2, 1, 3, 1;";
+    let with_staff = |body: &str| format!("{} {}", staff, body);
+    run!(
+        &with_staff("id <- Staff ? manager == 1"),
+        parse("id : 2, 3").unwrap()
+    );
+    run!(
+        &with_staff("id <- Staff ? not (manager == 1)"),
+        parse("id : nil").unwrap()
+    );
+    run!(
+        &with_staff("id <- Staff ? manager is null"),
+        parse("id : 1").unwrap()
+    );
+    run!(
+        &with_staff("nil # count, sum manager, min manager <- Staff"),
+        parse("count, sum_manager, min_manager : 3, 2, 1").unwrap()
+    );
+    run!(
+        "a, b : null, 1 >< a, c : null, 2",
+        parse("a, b, c : nil").unwrap()
+    );
+
+    // Unique columns allow repeated nulls, but keys can't be null
+    run!(
+        "a unique, b : null, 1, null, 2",
+        parse("a unique, b : null, 1, null, 2").unwrap()
+    );
+    fail!(
+        "a key : null",
+        Error::At(
+            Span { start: 0, end: 12 },
+            Box::new(Error::TypeMismatch {
+                expected: "a value in key column `a`".to_string(),
+                found: "null".to_string(),
+            })
+        )
+    );
+    run!("a int : null", parse("a int : null").unwrap());
+}
+
+#[test]
+fn test_strings() {
+    run!("length 'café'", Int(4));
+    run!("upper 'abc'", Str("ABC".to_string()));
+    run!("lower 'ABC'", Str("abc".to_string()));
+    run!("concat 'a' (concat 'b' 'c')", Str("abc".to_string()));
+    run!("contains 'Alice' 'lic'", Bool(true));
+    run!("starts_with 'Alice' 'li'", Bool(false));
+    run!("length null", Null);
+    run!("concat 'a' null", Null);
+
+    run!("'Alice' like 'A%'", Bool(true));
+    run!("'Alice' like '%c%'", Bool(true));
+    run!("'Alice' like 'A_ice'", Bool(true));
+    run!("'Alice' like 'a%'", Bool(false));
+    run!("'Alice' like '%e%e'", Bool(false));
+    run!("'aXbXc' like '%X%c'", Bool(true));
+    run!("'' like '%'", Bool(true));
+    run!(r"'100%' like '100\\%'", Bool(true));
+    run!(r"'1000' like '100\\%'", Bool(false));
+    run!("null like '%'", Null);
+
+    let staff = "Staff = id, name : 1, 'Alice', 2, 'Bob', 3, 'alan';";
+    run!(
+        &format!("{} id <- Staff ? lower name like 'a%'", staff),
+        parse("id : 1, 3").unwrap()
+    );
+    run!(
+        &format!("{} initial := upper name <+ Staff ? id == 3", staff),
+        parse("id, name, initial : 3, 'alan', 'ALAN'").unwrap()
+    );
+
+    fail!(
+        "length 1",
+        Error::At(
+            Span { start: 7, end: 8 },
+            Box::new(Error::TypeMismatch {
+                expected: "string".to_string(),
+                found: "integer".to_string(),
+            })
+        )
+    );
+    fail!(
+        "1 like 'a'",
+        Error::At(
+            Span { start: 0, end: 1 },
+            Box::new(Error::TypeMismatch {
+                expected: "string".to_string(),
+                found: "integer".to_string(),
+            })
+        )
+    );
+}
+
+#[test]
+fn test_errors() {
+    fail!(
+        "a : 1 ? a",
+        Error::At(
+            Span { start: 8, end: 9 },
+            Box::new(Error::TypeMismatch {
+                expected: "boolean".to_string(),
+                found: "integer".to_string(),
+            })
+        )
+    );
+
+    fail!(
+        "x = 1;\ny",
+        Error::At(
+            Span { start: 7, end: 8 },
+            Box::new(Error::UnknownVariable("y".to_string()))
+        )
+    );
+
+    fail!(
+        "a <- 1",
+        Error::At(
+            Span { start: 5, end: 6 },
+            Box::new(Error::TypeMismatch {
+                expected: "table".to_string(),
+                found: "integer".to_string(),
+            })
+        )
+    );
+
+    fail!(
+        "a : 1 + b : 2",
+        Error::At(
+            Span { start: 0, end: 13 },
+            Box::new(Error::ColumnMismatch {
+                op: "union".to_string(),
+                left: vec!["a".to_string()],
+                right: vec!["b".to_string()],
+            })
+        )
+    );
+}
+
+#[test]
+fn test_functions() {
+    run!("double x = x * 2; double 21", Int(42));
+    run!("add x y = x + y; add 1 (add 2 3)", Int(6));
+
+    // Functions keep the values of the variables they use where they're
+    // defined
+    run!("n = 1; f x = x + n; n = 10; f 1", Int(2));
+    run!("x = 1; f x = x; f 2", Int(2));
+
+    // Conditions see parameters and variables, but columns come first
+    run!(
+        "Staff = name, dept : 'Alice', 'x', 'Bob', 'y';
+        members d = name <- Staff ? dept == d;
+        members 'y'",
+        table(vec!["name".to_string()], vec![Str("Bob".to_string())])
+    );
+    run!(
+        "name = 'Bob'; t = name : 'Alice'; t ? name == 'Alice'",
+        table(vec!["name".to_string()], vec![Str("Alice".to_string())])
+    );
+    run!(
+        "limit = 1; t = a : 1, 2; t ? a > limit",
+        table(vec!["a".to_string()], vec![Int(2)])
+    );
+
+    fail!(
+        "f x = x; f 1 2",
+        Error::At(
+            Span { start: 9, end: 14 },
+            Box::new(Error::Arity {
+                function: "f".to_string(),
+                expected: 1,
+                found: 2,
+            })
+        )
+    );
+    fail!(
+        "foo 1",
+        Error::At(
+            Span { start: 0, end: 5 },
+            Box::new(Error::UnknownFunction("foo".to_string()))
+        )
+    );
+    fail!(
+        "x = 1; x 2",
+        Error::At(
+            Span { start: 7, end: 8 },
+            Box::new(Error::TypeMismatch {
+                expected: "function".to_string(),
+                found: "integer".to_string(),
+            })
+        )
+    );
+}
@@ -1,4 +1,13 @@
-use sdb::{parse, Exp::*};
+use sdb::{
+    parse, parse_spanned, Agg, Column, Error, Exp, Exp::*, Function, Key, Reference, Relation,
+    Span, Type, F64,
+};
+
+use chrono::{NaiveDate, NaiveTime, TimeDelta};
+
+fn table(vars: Vec<String>, cells: Vec<Exp>) -> Exp {
+    Table(Relation::from_cells(vars, cells).unwrap())
+}
 
 #[test]
 fn test_bool() {
@@ -6,23 +15,124 @@ fn test_bool() {
     assert_eq!(parse("false"), Ok(Bool(false)));
 }
 
+#[test]
+fn test_null() {
+    assert_eq!(parse("null"), Ok(Null));
+    assert_eq!(parse("nullable"), Ok(Var("nullable".to_string())));
+    assert_eq!(
+        parse("x is null && y"),
+        Ok(And(
+            Box::new(IsNull(Box::new(Var("x".to_string())))),
+            Box::new(Var("y".to_string()))
+        ))
+    );
+    assert_eq!(
+        parse("x is not null"),
+        Ok(Not(Box::new(IsNull(Box::new(Var("x".to_string()))))))
+    );
+}
+
 #[test]
 fn test_int() {
     assert_eq!(parse("123"), Ok(Int(123)));
     assert_eq!(parse("-42"), Ok(Int(-42)));
 }
 
+#[test]
+fn test_numbers() {
+    assert_eq!(parse("3.5"), Ok(Float(F64(3.5))));
+    assert_eq!(parse("-0.25"), Ok(Float(F64(-0.25))));
+    assert_eq!(parse("1e3"), Ok(Float(F64(1000.0))));
+    assert_eq!(parse("2.5E-1"), Ok(Float(F64(0.25))));
+    assert_eq!(
+        parse("3.50d"),
+        Ok(Decimal(rust_decimal::Decimal::new(350, 2)))
+    );
+    assert_eq!(parse("-7d"), Ok(Decimal(rust_decimal::Decimal::new(-7, 0))));
+    assert!(parse("1e999").is_err());
+    assert!(parse("3.").is_err());
+
+    // A number that doesn't fit its type is an error, not something else
+    let error = |text: &str| parse(text).unwrap_err().to_string();
+    assert!(error("1.00000000000000000000000000001d")
+        .contains("`1.00000000000000000000000000001d` is too large or precise for a decimal"));
+    assert!(error("x : 1e999").contains("`1e999` is too large for a float"));
+    assert!(error("99999999999999999999 + 1").contains("`99999999999999999999` is too large"));
+}
+
+#[test]
+fn test_times() {
+    let date = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+    let time = NaiveTime::from_hms_opt(9, 13, 0).unwrap();
+    assert_eq!(parse("date '2026-10-18'"), Ok(Date(date)));
+    assert_eq!(parse("time '09:13'"), Ok(Time(time)));
+    assert_eq!(
+        parse("timestamp '2026-10-18T09:13:00Z'"),
+        Ok(Timestamp(date.and_time(time).and_utc()))
+    );
+    assert_eq!(
+        parse("timestamp '2026-10-18'"),
+        Ok(Timestamp(date.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+    );
+    assert_eq!(
+        parse("interval '1 week -2 days 1.5 seconds'"),
+        Ok(Interval(
+            TimeDelta::new(5 * 86400 + 1, 500_000_000).unwrap()
+        ))
+    );
+    assert_eq!(
+        parse("date d"),
+        Ok(Call(Function::Date, vec![Var("d".to_string())]))
+    );
+    assert_eq!(parse("now"), Ok(Call(Function::Now, vec![])));
+    assert_eq!(parse("now()"), Ok(Call(Function::Now, vec![])));
+
+    let error = |text: &str| parse(text).unwrap_err().to_string();
+    assert_eq!(
+        error("date '18/10/2026'"),
+        "expected a date like '2026-10-18', found '18/10/2026'"
+    );
+    assert_eq!(
+        error("time '25:00'"),
+        "expected a time like '09:13:00', found '25:00'"
+    );
+    assert_eq!(
+        error("interval '2 fortnights'"),
+        "expected an interval like '2 days 3 hours', found '2 fortnights'"
+    );
+    assert_eq!(
+        error("interval '1.5 days'"),
+        "expected an interval like '2 days 3 hours', found '1.5 days'"
+    );
+    assert_eq!(error("()"), "`()` is only allowed after a function");
+    assert_eq!(error("now 1"), "`now` takes 0 arguments, found 1");
+}
+
 #[test]
 fn test_str() {
     assert_eq!(parse("''"), Ok(Str("".to_string())));
     assert_eq!(parse("'hello'"), Ok(Str("hello".to_string())));
     assert_eq!(parse("'hello world'"), Ok(Str("hello world".to_string())));
+    assert_eq!(parse(r"'O\'Brien'"), Ok(Str("O'Brien".to_string())));
+    assert_eq!(
+        parse(r"'a\\b\nc\td\re'"),
+        Ok(Str("a\\b\nc\td\re".to_string()))
+    );
+    assert_eq!(
+        parse(r"'caf\u{e9} \u{1F600}'"),
+        Ok(Str("café 😀".to_string()))
+    );
+    assert!(parse(r"'\q'").is_err());
+    assert!(parse(r"'\u{110000}'").is_err());
+    assert!(parse(r"'unfinished\'").is_err());
 }
 
 #[test]
 fn test_var() {
     assert_eq!(parse("x"), Ok(Var("x".to_string())));
     assert_eq!(parse("_x_1"), Ok(Var("_x_1".to_string())));
+    assert_eq!(parse("Staff.id"), Ok(Var("Staff.id".to_string())));
+    assert_eq!(parse("online"), Ok(Var("online".to_string())));
 }
 
 #[test]
@@ -38,7 +148,7 @@ fn test_comment() {
 fn test_program() {
     let program = Let(
         "Staff".to_string(),
-        Box::new(Table(
+        Box::new(table(
             vec!["name".to_string(), "id".to_string(), "employed".to_string()],
             vec![
                 Str("Alice".to_string()),
@@ -119,3 +229,356 @@ alice_or_bob_employed
         Ok(program),
     );
 }
+
+#[test]
+fn test_precedence() {
+    let var = |name: &str| Box::new(Var(name.to_string()));
+    // `+` binds more loosely than `-`
+    assert_eq!(
+        parse("a + b - c"),
+        Ok(Union(var("a"), Box::new(Difference(var("b"), var("c")))))
+    );
+    // Comparisons bind more tightly than `&&`, and `&&` than `||`
+    assert_eq!(
+        parse("a == b && c"),
+        Ok(And(Box::new(Equals(var("a"), var("b"))), var("c")))
+    );
+    assert_eq!(
+        parse("age > 30 && age < 50"),
+        Ok(And(
+            Box::new(Greater(var("age"), Box::new(Int(30)))),
+            Box::new(Less(var("age"), Box::new(Int(50))))
+        ))
+    );
+    assert_eq!(
+        parse("a || b && c >= 1"),
+        Ok(Or(
+            var("a"),
+            Box::new(And(
+                var("b"),
+                Box::new(GreaterEquals(var("c"), Box::new(Int(1))))
+            ))
+        ))
+    );
+    // Arithmetic binds more tightly than comparisons
+    assert_eq!(
+        parse("age + 1 == 41"),
+        Ok(Equals(
+            Box::new(Union(var("age"), Box::new(Int(1)))),
+            Box::new(Int(41))
+        ))
+    );
+    assert_eq!(
+        parse("x + 1 > 2"),
+        Ok(Greater(
+            Box::new(Union(var("x"), Box::new(Int(1)))),
+            Box::new(Int(2))
+        ))
+    );
+    assert_eq!(
+        parse("t ? x * 2 <= y - 1"),
+        Ok(Where(
+            var("t"),
+            Box::new(LessEquals(
+                Box::new(Product(var("x"), Box::new(Int(2)))),
+                Box::new(Difference(var("y"), Box::new(Int(1))))
+            ))
+        ))
+    );
+    // and more loosely than table literals
+    assert_eq!(
+        parse("t + a : 1"),
+        Ok(Union(
+            var("t"),
+            Box::new(table(vec!["a".to_string()], vec![Int(1)]))
+        ))
+    );
+    // while cells can compare and use `&&` and `||`
+    assert_eq!(
+        parse("t + a : x == 1, y || z"),
+        Ok(Union(
+            var("t"),
+            Box::new(table(
+                vec!["a".to_string()],
+                vec![Equals(var("x"), Box::new(Int(1))), Or(var("y"), var("z"))]
+            ))
+        ))
+    );
+    // A computed column takes everything up to the next column
+    assert_eq!(
+        parse("b := a + 1 <+ t"),
+        Ok(Extend(
+            vec![("b".to_string(), Union(var("a"), Box::new(Int(1))))],
+            var("t")
+        ))
+    );
+    assert_eq!(
+        parse("b := a + 1 > 2, c := x : 1, 2 <+ t"),
+        Ok(Extend(
+            vec![
+                (
+                    "b".to_string(),
+                    Greater(
+                        Box::new(Union(var("a"), Box::new(Int(1)))),
+                        Box::new(Int(2))
+                    )
+                ),
+                (
+                    "c".to_string(),
+                    table(vec!["x".to_string()], vec![Int(1), Int(2)])
+                ),
+            ],
+            var("t")
+        ))
+    );
+    let error = parse("a + 1 <+ t").unwrap_err();
+    assert_eq!(error.to_string(), "expected a column like `name := exp`");
+}
+
+#[test]
+fn test_functions() {
+    assert_eq!(
+        parse("active d = Staff ? dept == d; active 'x'"),
+        Ok(Let(
+            "active".to_string(),
+            Box::new(Closure(Box::new(sdb::Closure {
+                name: "active".to_string(),
+                params: vec!["d".to_string()],
+                body: Box::new(Where(
+                    Box::new(Var("Staff".to_string())),
+                    Box::new(Equals(
+                        Box::new(Var("dept".to_string())),
+                        Box::new(Var("d".to_string()))
+                    ))
+                )),
+                env: vec![],
+            }))),
+            Box::new(Apply(
+                Box::new(Var("active".to_string())),
+                vec![Str("x".to_string())]
+            ))
+        ))
+    );
+    assert_eq!(
+        parse("f (g 1) 2"),
+        Ok(Apply(
+            Box::new(Var("f".to_string())),
+            vec![Apply(Box::new(Var("g".to_string())), vec![Int(1)]), Int(2)]
+        ))
+    );
+    assert_eq!(
+        parse("f ()"),
+        Ok(Apply(Box::new(Var("f".to_string())), vec![]))
+    );
+
+    let error = parse("f 1 = 2; f").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected a function name and parameters before `=`"
+    );
+    let error = parse("f x x = x; f").unwrap_err();
+    assert_eq!(error.to_string(), "parameter `x` is repeated");
+    let error = parse("1 2").unwrap_err();
+    assert_eq!(error.to_string(), "expected function, found integer");
+}
+
+#[test]
+fn test_types() {
+    let typed = |columns| {
+        let cells = vec![Int(1), Var("x".to_string())];
+        let table = Relation::from_cells(vec!["id".to_string(), "name".to_string()], cells);
+        Ok(Table(table.unwrap().declare(columns).unwrap()))
+    };
+    assert_eq!(
+        parse("id int, name str : 1, x"),
+        typed(vec![Column::typed(Type::Int), Column::typed(Type::Str)])
+    );
+    assert_eq!(
+        parse("id int, name any : 1, x"),
+        typed(vec![Column::typed(Type::Int), Column::default()])
+    );
+    assert_eq!(
+        parse("id int key, name unique : 1, x"),
+        typed(vec![
+            Column {
+                ty: Type::Int,
+                key: Some(Key::Primary),
+                references: None,
+            },
+            Column {
+                ty: Type::Unknown,
+                key: Some(Key::Unique),
+                references: None,
+            },
+        ])
+    );
+    assert_eq!(
+        parse("id int, name references Staff.name : 1, x"),
+        typed(vec![
+            Column::typed(Type::Int),
+            Column {
+                references: Some(Reference {
+                    var: "Staff".to_string(),
+                    column: "name".to_string(),
+                }),
+                ..Column::default()
+            },
+        ])
+    );
+    assert_eq!(
+        parse("schema Staff"),
+        Ok(Schema(Box::new(Var("Staff".to_string()))))
+    );
+}
+
+#[test]
+fn test_error() {
+    let error = parse("x = 1;\nx ?").unwrap_err();
+    assert_eq!(error.category(), "parse");
+    assert_eq!(error.span(), Some(Span { start: 9, end: 10 }));
+    assert_eq!(error.span().unwrap().location("x = 1;\nx ?"), (2, 3));
+    // Offsets past the source or inside a character are clamped
+    assert_eq!(Span { start: 6, end: 7 }.location("ééé"), (1, 4));
+    assert_eq!(Span { start: 5, end: 7 }.location("ééé"), (1, 3));
+
+    let error = parse("1 +").unwrap_err();
+    assert_eq!(error.to_string(), "unexpected `+`");
+
+    let error = parse("lower s = s; 1").unwrap_err();
+    assert_eq!(error.category(), "name");
+    assert_eq!(error.span(), Some(Span { start: 0, end: 14 }));
+
+    let error = parse("a, b : 1, 2, 3").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected 2 values in each row of (a, b), found a row of 1"
+    );
+    assert_eq!(error.span(), Some(Span { start: 0, end: 14 }));
+
+    let error = parse("id int : 'x'").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected integer in column `id`, found string"
+    );
+    let error = parse("id integer : 1").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "unknown type `integer`, expected int, float, decimal, str, bool, date, time, timestamp, interval or any"
+    );
+
+    let error = parse("id key int : 1").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the type of `id` must come first, and only once"
+    );
+    let error = parse("id references Staff : 1").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected a column like `Staff.id` after `references`"
+    );
+    let error = parse("a key, b key : 1, 2, 1, 2").unwrap_err();
+    assert_eq!(error.category(), "constraint");
+    assert_eq!(
+        error.to_string(),
+        "more than one row with `a` = 1 and `b` = 2"
+    );
+
+    let error = parse("concat 'a'").unwrap_err();
+    assert_eq!(error.category(), "type");
+    assert_eq!(error.to_string(), "`concat` takes 2 arguments, found 1");
+    assert_eq!(error.span(), Some(Span { start: 0, end: 10 }));
+    let error = parse("not a b").unwrap_err();
+    assert_eq!(error.to_string(), "`not` takes 1 argument, found 2");
+
+    let error = parse("x is 1").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected `null` or `not null` after `is`"
+    );
+    assert_eq!(error.span(), Some(Span { start: 0, end: 6 }));
+
+    let error = parse("a, a : 1, 2").unwrap_err();
+    assert_eq!(error.to_string(), "more than one column named `a`");
+    assert!(parse("nil : 1").is_err());
+}
+
+#[test]
+fn test_spanned() {
+    assert_eq!(
+        parse_spanned("not x"),
+        Ok(Spanned(
+            Span { start: 0, end: 5 },
+            Box::new(Not(Box::new(Spanned(
+                Span { start: 4, end: 5 },
+                Box::new(Var("x".to_string()))
+            ))))
+        ))
+    );
+}
+
+#[test]
+fn test_version() {
+    assert_eq!(
+        parse("Staff@3"),
+        Ok(Version("Staff".to_string(), sdb::Version::Number(3)))
+    );
+    assert_eq!(
+        parse("Staff@'2026-10-01'"),
+        Ok(Version(
+            "Staff".to_string(),
+            sdb::Version::Time("2026-10-01".to_string())
+        ))
+    );
+    assert_eq!(
+        parse("not Staff@1"),
+        Ok(Not(Box::new(Version(
+            "Staff".to_string(),
+            sdb::Version::Number(1)
+        ))))
+    );
+    assert!(parse("Staff@0").is_err());
+    assert!(parse("Staff@'yesterday'").is_err());
+    assert!(parse("1@1").is_err());
+}
+
+#[test]
+fn test_group() {
+    assert_eq!(
+        parse("dept # count, avg (salary) <- Staff"),
+        Ok(Group(
+            vec!["dept".to_string()],
+            vec![Agg::Count, Agg::Avg("salary".to_string())],
+            Box::new(Var("Staff".to_string()))
+        ))
+    );
+    assert_eq!(
+        parse("nil # min a, max b <- t"),
+        Ok(Group(
+            vec![],
+            vec![Agg::Min("a".to_string()), Agg::Max("b".to_string())],
+            Box::new(Var("t".to_string()))
+        ))
+    );
+    assert_eq!(
+        parse("a # median b <- t").map_err(|e| e.to_string()),
+        Err(Error::UnknownFunction("median".to_string()).to_string())
+    );
+    assert!(parse("a # count").is_err());
+}
+
+#[test]
+fn test_join() {
+    assert_eq!(
+        parse("a >< b on c"),
+        Ok(JoinOn(
+            Box::new(Var("a".to_string())),
+            Box::new(Var("b".to_string())),
+            Box::new(Var("c".to_string()))
+        ))
+    );
+    assert_eq!(
+        parse("not on_time"),
+        Ok(Not(Box::new(Var("on_time".to_string()))))
+    );
+    assert!(parse("a * b on c").is_err());
+}
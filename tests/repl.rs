@@ -0,0 +1,74 @@
+use sdb::{is_complete, Session};
+
+use std::fs;
+
+#[test]
+fn test_is_complete() {
+    assert!(is_complete("x"));
+    assert!(is_complete("(x)"));
+    assert!(!is_complete("(x"));
+    assert!(!is_complete("x = 1;"));
+    assert!(!is_complete("x = 1; -- comment"));
+    assert!(is_complete("x = 1;\n"));
+    assert!(is_complete("x = 1;\n  "));
+    assert!(is_complete("x = ';'"));
+    assert!(!is_complete("x = 'abc"));
+    assert!(is_complete(r"x = 'it\'s'"));
+    assert!(!is_complete(r"x = 'it\'"));
+    assert!(is_complete(r"x = 'a\\'"));
+    assert!(!is_complete("x /* comment"));
+    assert!(is_complete("x /* ( */"));
+}
+
+#[test]
+fn test_session() {
+    let mut session = Session::new(None);
+    assert_eq!(session.input("x = 1;"), Ok("".to_string()));
+    assert_eq!(session.input("y = x;\n"), Ok("".to_string()));
+    assert_eq!(session.input("x == y"), Ok("true".to_string()));
+    assert_eq!(session.input("y"), Ok("1".to_string()));
+    assert_eq!(session.input("z = 2;\nz"), Ok("2".to_string()));
+    assert_eq!(session.input("z"), Ok("2".to_string()));
+    assert!(session.input("w").is_err());
+}
+
+#[test]
+fn test_function_errors() {
+    // A function's error is shown against the line that applied it, which
+    // its definition's offsets don't fit
+    let mut session = Session::new(None);
+    session.input("f x = x + 1;\n").unwrap();
+    let error = session.input("f 'éééééééé'").unwrap_err();
+    assert!(error.contains("expected table or number, found string"));
+}
+
+#[test]
+fn test_commands() {
+    let mut session = Session::new(None);
+    session.input("Staff = id, name : 1, 'Alice';").unwrap();
+    session.input("n = 1;").unwrap();
+    assert_eq!(
+        session.input(":env"),
+        Ok("Staff : table (id integer, name string)\nn : integer".to_string())
+    );
+    assert_eq!(
+        session.input(":type name <- Staff"),
+        Ok("table (name string)".to_string())
+    );
+    assert_eq!(
+        session.input(":type id, name : 1, 'a', 'b', 'c'"),
+        Ok("table (id, name string)".to_string())
+    );
+    assert_eq!(session.input(":type n == 1"), Ok("boolean".to_string()));
+    assert!(session.input(":type Staff ? id").is_err());
+    assert!(session.input(":frobnicate").is_err());
+
+    let path = std::env::temp_dir().join(format!("sdb-test-load-{}.sdb", std::process::id()));
+    fs::write(&path, "m = 'two';\n").unwrap();
+    assert_eq!(
+        session.input(&format!(":load {}", path.display())),
+        Ok("".to_string())
+    );
+    assert_eq!(session.input("m"), Ok("'two'".to_string()));
+    fs::remove_file(&path).unwrap();
+}
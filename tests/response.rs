@@ -0,0 +1,35 @@
+use sdb::Response;
+
+macro_rules! run {
+    ($response:expr) => {{
+        let response = $response;
+        assert_eq!(Response::decode(&response.encode()), response);
+    }};
+}
+
+#[test]
+fn test_round_trip() {
+    run!(Response::Ok("1".to_string()));
+    run!(Response::Ok("".to_string()));
+    run!(Response::Ok("a, b : 1, 2".to_string()));
+    run!(Response::Err {
+        category: "eval".to_string(),
+        message: "expected table".to_string(),
+    });
+    run!(Response::Err {
+        category: "parse".to_string(),
+        message: "line one\nline two".to_string(),
+    });
+}
+
+#[test]
+fn test_malformed() {
+    assert!(matches!(
+        Response::decode("garbage"),
+        Response::Err { category, .. } if category == "protocol"
+    ));
+    assert!(matches!(
+        Response::decode(""),
+        Response::Err { category, .. } if category == "protocol"
+    ));
+}
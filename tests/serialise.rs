@@ -1,4 +1,8 @@
-use sdb::{parse, serialise};
+use sdb::{eval, parse, serialise, Env, Exp, Relation, F64};
+
+use chrono::{DateTime, TimeDelta};
+use proptest::prelude::*;
+use rust_decimal::Decimal;
 
 macro_rules! run {
     ($input:expr, $output:expr) => {{
@@ -33,10 +37,175 @@ fn test_serialise() {
     run!("(a * b) * c", "a * b * c");
     run!("a * (b * c)", "a * (b * c)");
 
+    run!("id int, name str : 1, 'a'", "id int, name str : 1, 'a'");
+    run!("id any, ok bool : 1, true", "id, ok bool : 1, true");
+    run!("schema (a <- t)", "schema (a <- t)");
+    run!(
+        "id int key, email unique, n : 1, 'a', 2",
+        "id int key, email unique, n : 1, 'a', 2"
+    );
+    run!(
+        "staff_id int key references Staff.id : 1",
+        "staff_id int key references Staff.id : 1"
+    );
+
+    run!("a int, b : null, 1", "a int, b : null, 1");
+    run!("t ? a is null || b", "t ? a is null || b");
+    run!("not (a is not null)", "not (a is not null)");
+    run!("(a + 1) is null", "(a + 1) is null");
+
+    run!(r"'O\'Brien'", r"'O\'Brien'");
+    run!(r"'a\u{5c}\u{0}b'", r"'a\\\u{0}b'");
+
+    run!("concat (lower a) 'b'", "concat (lower a) 'b'");
+    run!("t ? length(name) > 3", "t ? length name > 3");
+    run!("a like 'x%' && b", "a like 'x%' && b");
+    run!("not (a like b)", "not (a like b)");
+
+    run!("1.5 + 2e-3 * 1e300", "1.5 + 0.002 * 1e300");
+    run!("-0.0", "-0.0");
+    run!("3.50d - -1d", "3.50d - -1d");
+    run!(
+        "x float, y decimal : 1.0, 2d",
+        "x float, y decimal : 1.0, 2d"
+    );
+
     run!("nil", "nil");
     run!("nil : nil", "nil");
     run!("nil <- a : 1", "nil <- a : 1");
 
     run!("a : b || c, d || e", "a : b || c, d || e");
     run!("a : (b : c), (d : e)", "a : (b : c), (d : e)");
+
+    run!(
+        "dept # count, sum(salary) <- Staff",
+        "dept # count, sum salary <- Staff"
+    );
+    run!("nil # count <- a + b", "nil # count <- a + b");
+    run!("a, b # nil <- c", "a, b # nil <- c");
+
+    run!("a >< b * c", "a >< b * c");
+    run!("(a * b) >< c", "(a * b) >< c");
+    run!("a >< b on a.x == b.y", "a >< b on a.x == b.y");
+    run!("(a >< b on c) >< d on e", "(a >< b on c) >< d on e");
+    run!("x <- a >< b on c ? d", "x <- a >< b on c ? d");
+
+    run!("a -> b, c -> d <~ t", "a -> b, c -> d <~ t");
+    run!("a -> b <~ (x <- t + u)", "a -> b <~ (x <- t + u)");
+    run!("x <- a -> x <~ t", "x <- a -> x <~ t");
+    run!("(a -> b <~ t) - u", "(a -> b <~ t) - u");
+
+    run!("a := b || c, d := 1 <+ t", "a := b || c, d := 1 <+ t");
+    run!("a := (x : 1) <+ t ? c", "a := (x : 1) <+ t ? c");
+
+    run!("a == b && c == d", "a == b && c == d");
+    run!("(a || b) && c", "(a || b) && c");
+    run!("a < b || a >= c", "a < b || a >= c");
+    run!("(a + 1) <= b", "a + 1 <= b");
+    run!("(a < b) && c", "a < b && c");
+    run!("a < (b && c)", "a < (b && c)");
+    run!("(a + 1) == b", "a + 1 == b");
+    run!("(a == b) + 1", "(a == b) + 1");
+    run!("(a + b) - c", "(a + b) - c");
+    run!("a + b - c", "a + b - c");
+    run!("b := (a + 1) <+ t", "b := a + 1 <+ t");
+    run!(
+        "b := (a > 1), c := (x = 1; x) <+ t",
+        "b := a > 1, c := (x = 1; x) <+ t"
+    );
+    run!("a - (b + c)", "a - (b + c)");
+    run!("(a - b) + c", "a - b + c");
+    run!("a / b * c", "a / b * c");
+    run!("a / (b * c)", "a / (b * c)");
+    run!("t ? a > 1", "t ? a > 1");
+
+    run!("Staff @ 3", "Staff@3");
+    run!(
+        "Staff@'2026-10-01T12:00:00Z' + a",
+        "Staff@'2026-10-01T12:00:00Z' + a"
+    );
+
+    run!("date '2026-10-18'", "date '2026-10-18'");
+    run!("time '9:05'", "time '09:05:00'");
+    run!("time '23:59:59.25'", "time '23:59:59.250'");
+    run!(
+        "timestamp '2026-10-18T11:13:00+02:00'",
+        "timestamp '2026-10-18T09:13:00Z'"
+    );
+    run!("interval '1 week 1 second'", "interval '7 days 1 second'");
+    run!(
+        "interval '-90 minutes -0.5 seconds'",
+        "interval '-1 hour -30 minutes -0.5 seconds'"
+    );
+    run!("interval '0 days'", "interval '0 seconds'");
+    run!("now", "now");
+    run!("now()", "now");
+    run!("a := date d <+ t", "a := date d <+ t");
+
+    run!("f x y = x + y; f 1 (f 2 3)", "f x y = x + y; f 1 (f 2 3)");
+    run!("f x = (a = x; a); f", "f x = (a = x; a); f");
+    run!("f = x : 1; f ()", "f = x : 1; f ()");
+    run!("f (-5)", "f (-5)");
+    run!("f (-1.5) (-2d)", "f (-1.5) (-2d)");
+    run!("not (-1)", "not (-1)");
+    run!("length (-0.0) - 1", "length (-0.0) - 1");
+}
+
+#[test]
+fn test_closures() {
+    // A function is written with the values it captured, and reading that
+    // back gives the same function
+    let text = "n = 1; g x = x + n; f x = g x; f";
+    let (closure, _) = eval(&parse(text).unwrap(), &Env::new()).unwrap();
+    let serialised = serialise(closure.clone());
+    assert_eq!(serialised, "g = (n = 1; g x = x + n; g); f x = g x; f");
+    assert_eq!(
+        eval(&parse(&serialised).unwrap(), &Env::new()).map(|(exp, _)| exp),
+        Ok(closure)
+    );
+}
+
+proptest! {
+    #[test]
+    fn test_strings(str in any::<String>()) {
+        let serialised = serialise(Exp::Str(str.clone()));
+        // Persisted values are one line each
+        prop_assert!(!serialised.contains('\n'));
+        prop_assert_eq!(parse(&serialised), Ok(Exp::Str(str)));
+    }
+
+    #[test]
+    fn test_floats(float in any::<f64>().prop_filter("finite", |f| f.is_finite())) {
+        let exp = Exp::Float(F64(float));
+        let parsed = parse(&serialise(exp)).unwrap();
+        // Compare bits, so that -0.0 isn't mistaken for 0.0
+        prop_assert!(matches!(parsed, Exp::Float(F64(f)) if f.to_bits() == float.to_bits()));
+    }
+
+    #[test]
+    fn test_decimals(mantissa in any::<i64>(), scale in 0u32..=28) {
+        let decimal = Decimal::new(mantissa, scale);
+        let parsed = parse(&serialise(Exp::Decimal(decimal))).unwrap();
+        // Equal decimals can differ in scale, so compare that too
+        prop_assert!(matches!(parsed, Exp::Decimal(d) if d == decimal && d.scale() == scale));
+    }
+
+    #[test]
+    fn test_intervals(secs in -1_000_000_000_000i64..1_000_000_000_000, nanos in 0u32..1_000_000_000) {
+        let interval = Exp::Interval(TimeDelta::new(secs, nanos).unwrap());
+        prop_assert_eq!(parse(&serialise(interval.clone())), Ok(interval));
+    }
+
+    #[test]
+    fn test_timestamps(secs in 0i64..253_402_300_800, nanos in 0u32..1_000_000_000) {
+        let timestamp = Exp::Timestamp(DateTime::from_timestamp(secs, nanos).unwrap());
+        prop_assert_eq!(parse(&serialise(timestamp.clone())), Ok(timestamp));
+    }
+
+    #[test]
+    fn test_string_tables(cells in prop::collection::vec(any::<String>(), 0..8)) {
+        let cells = cells.into_iter().map(Exp::Str).collect();
+        let table = Exp::Table(Relation::from_cells(vec!["a".to_string()], cells).unwrap());
+        prop_assert_eq!(parse(&serialise(table.clone())), Ok(table));
+    }
 }
@@ -0,0 +1,283 @@
+mod common;
+
+use common::{open, temp_dir};
+use sdb::{execute, parse, Exp};
+
+use std::{fs, sync::Arc};
+
+fn count(exp: Exp) -> usize {
+    match exp {
+        Exp::Table(table) => table.len(),
+        _ => 0,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_appends() {
+    let (conf, store, run) = open(&temp_dir("concurrent-appends")).await;
+    run("Staff = id : 0; nil").await.unwrap();
+
+    let tasks = (1..=100)
+        .map(|id| {
+            let conf = Arc::clone(&conf);
+            let store = Arc::clone(&store);
+            tokio::spawn(async move {
+                let text = format!("Staff = Staff + id : {}; nil", id);
+                execute(&text, &conf, &store).await.unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(count(run("Staff").await.unwrap()), 101);
+    for id in 0..=100 {
+        let text = format!("Staff ? id == {}", id);
+        assert_eq!(count(run(&text).await.unwrap()), 1);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_transfers() {
+    let (conf, store, run) = open(&temp_dir("concurrent-transfers")).await;
+    run("A = n : 0; B = n : 0; nil").await.unwrap();
+
+    // Each program moves a row from one variable to the other in opposite
+    // orders, which would deadlock if locks weren't taken in a fixed order
+    let tasks = (1..=50)
+        .map(|i| {
+            let conf = Arc::clone(&conf);
+            let store = Arc::clone(&store);
+            tokio::spawn(async move {
+                let text = if i % 2 == 0 {
+                    format!("A = A + n : {}; B = B + n : {}; nil", i, i)
+                } else {
+                    format!("B = B + n : {}; A = A + n : {}; nil", i, i)
+                };
+                execute(&text, &conf, &store).await.unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(count(run("A").await.unwrap()), 51);
+    assert_eq!(count(run("B").await.unwrap()), 51);
+}
+
+#[tokio::test]
+async fn test_time_travel() {
+    let (_, _, run) = open(&temp_dir("time-travel")).await;
+    run("Staff = id : 1; nil").await.unwrap();
+    run("Staff = Staff + id : 2; nil").await.unwrap();
+    run("Staff = Staff - id : 1; nil").await.unwrap();
+
+    assert_eq!(run("Staff@1").await, Ok(parse("id : 1").unwrap()));
+    assert_eq!(run("Staff@2").await, Ok(parse("id : 1, 2").unwrap()));
+    assert_eq!(run("Staff").await, Ok(parse("id : 2").unwrap()));
+    assert!(run("Staff@4").await.is_err());
+
+    // Undo the last write by shadowing with an earlier version
+    run("Staff = Staff@2; nil").await.unwrap();
+    assert_eq!(run("Staff").await, Ok(parse("id : 1, 2").unwrap()));
+    assert_eq!(run("Staff@4").await, Ok(parse("id : 1, 2").unwrap()));
+}
+
+#[tokio::test]
+async fn test_check_before_write() {
+    let (_, _, run) = open(&temp_dir("check-before-write")).await;
+    run("Staff = id : 1; nil").await.unwrap();
+
+    // The bad condition is never evaluated, since no rows are left by then
+    assert!(run("Staff = Staff ? id == 2; Staff = Staff ? id; nil")
+        .await
+        .is_err());
+    assert_eq!(run("Staff").await, Ok(parse("id : 1").unwrap()));
+}
+
+#[tokio::test]
+async fn test_schema() {
+    let (_, _, run) = open(&temp_dir("schema")).await;
+    run("Staff = id int, name str : 1, 'Alice'; nil")
+        .await
+        .unwrap();
+
+    // Shadowing keeps the declared types, even without repeating them
+    run("Staff = id, name : 2, 'Bob'; nil").await.unwrap();
+    assert_eq!(
+        run("column, type <- schema Staff").await,
+        Ok(parse("column str, type str : 'id', 'int', 'name', 'str'").unwrap())
+    );
+    assert!(run("Staff = id, name : 'three', 'Carol'; nil")
+        .await
+        .is_err());
+    assert!(run("Staff = id : 3; nil").await.is_err());
+    assert!(run("Staff = 3; nil").await.is_err());
+    assert_eq!(
+        run("Staff").await,
+        Ok(parse("id int, name str : 2, 'Bob'").unwrap())
+    );
+
+    // Declaring new types changes the schema
+    run("Staff = id str, name : 'three', 'Carol'; nil")
+        .await
+        .unwrap();
+    assert_eq!(
+        run("Staff").await,
+        Ok(parse("id str, name str : 'three', 'Carol'").unwrap())
+    );
+}
+
+#[tokio::test]
+async fn test_keys() {
+    let (_, _, run) = open(&temp_dir("keys")).await;
+    run("Staff = id int key, name : 1, 'Alice'; nil")
+        .await
+        .unwrap();
+
+    let error = run("Staff = Staff + id, name : 1, 'Dup'; nil")
+        .await
+        .unwrap_err();
+    assert_eq!(error.category(), "constraint");
+    assert_eq!(error.to_string(), "more than one row with `id` = 1");
+
+    // The key is kept by a value that doesn't declare one
+    let error = run("Staff = id, name : 2, 'Bob', 2, 'Dup'; nil")
+        .await
+        .unwrap_err();
+    assert_eq!(error.category(), "constraint");
+    assert_eq!(
+        run("Staff").await,
+        Ok(parse("id int key, name : 1, 'Alice'").unwrap())
+    );
+
+    run("Staff = Staff + id, name : 2, 'Bob'; nil")
+        .await
+        .unwrap();
+    assert_eq!(count(run("Staff").await.unwrap()), 2);
+}
+
+#[tokio::test]
+async fn test_references() {
+    let dir = temp_dir("references");
+    let (_, _, run) = open(&dir).await;
+    run("Staff = id int key : 1, 2; nil").await.unwrap();
+    run("Assignments = staff_id int references Staff.id, task : 1, 'a', 2, 'b'; nil")
+        .await
+        .unwrap();
+
+    // Neither side of a reference can be written so that it breaks
+    let error = run("Assignments = Assignments + staff_id, task : 3, 'c'; nil")
+        .await
+        .unwrap_err();
+    assert_eq!(error.category(), "constraint");
+    assert_eq!(
+        error.to_string(),
+        "`Assignments.staff_id` refers to 3, which isn't a value of `Staff.id`"
+    );
+    assert!(run("Staff = Staff - id : 2; nil").await.is_err());
+    assert_eq!(count(run("Staff").await.unwrap()), 2);
+
+    // A program that writes both sides is checked as a whole
+    run("Staff = Staff - id : 2; Assignments = Assignments ? not (staff_id == 2); nil")
+        .await
+        .unwrap();
+    assert_eq!(count(run("Staff").await.unwrap()), 1);
+    assert_eq!(count(run("Assignments").await.unwrap()), 1);
+
+    // A null reference is allowed, and persists
+    run("Assignments = Assignments + staff_id, task : null, 'c'; nil")
+        .await
+        .unwrap();
+    assert_eq!(
+        count(run("Assignments ? staff_id is null").await.unwrap()),
+        1
+    );
+
+    // References are found again when the store is reopened
+    let (_, _, run) = open(&dir).await;
+    assert!(run("Staff = Staff - id : 1; nil").await.is_err());
+}
+
+#[tokio::test]
+async fn test_times() {
+    let dir = temp_dir("times");
+    let (_, _, run) = open(&dir).await;
+    run("Staff = name str, hired date : 'Alice', (date '2019-04-01'); nil")
+        .await
+        .unwrap();
+    let text = fs::read_to_string(dir.join("^staff").join("1")).unwrap();
+    assert!(text.ends_with("name str, hired date : 'Alice', date '2019-04-01'"));
+    assert_eq!(
+        run("name <- Staff ? hired < date '2020-01-01'").await,
+        Ok(parse("name str : 'Alice'").unwrap())
+    );
+    assert!(run("Staff = name, hired : 'Bob', '2024-09-15'; nil")
+        .await
+        .is_err());
+
+    // `now` is evaluated when the program runs, so the log keeps the time
+    run("Log = at : now; nil").await.unwrap();
+    assert_eq!(count(run("Log ? at <= now").await.unwrap()), 1);
+}
+
+#[tokio::test]
+async fn test_functions() {
+    let dir = temp_dir("functions");
+    let (_, _, run) = open(&dir).await;
+    run("Staff = name, dept, employed : 'Alice', 'x', true, 'Bob', 'x', false; nil")
+        .await
+        .unwrap();
+    run("active d = name <- Staff ? employed && dept == d; nil")
+        .await
+        .unwrap();
+    let text = fs::read_to_string(dir.join("active").join("1")).unwrap();
+    assert!(text.ends_with("active d = name <- Staff ? employed && dept == d; active"));
+    assert_eq!(
+        run("active 'x'").await,
+        Ok(parse("name : 'Alice'").unwrap())
+    );
+
+    // Stored functions see the latest values of the variables they use
+    run("Staff = Staff + name, dept, employed : 'Carol', 'x', true; nil")
+        .await
+        .unwrap();
+    assert_eq!(count(run("active 'x'").await.unwrap()), 2);
+    assert!(run("active 'x' 'y'").await.is_err());
+
+    // Unless the program that defined them gave those variables new values
+    run("n = 1; add x = x + n; n = 10; nil").await.unwrap();
+    assert_eq!(run("add 1").await, Ok(Exp::Int(2)));
+    assert_eq!(run("n").await, Ok(Exp::Int(10)));
+
+    // Negative arguments keep their parentheses when stored
+    run("neg x = 0 - x; inc y = neg (-1) + y; nil")
+        .await
+        .unwrap();
+    assert_eq!(run("inc 1").await, Ok(Exp::Int(2)));
+
+    // Functions using functions read everything they need
+    run("staffed d = active d + active 'y'; nil").await.unwrap();
+    assert_eq!(count(run("staffed 'x'").await.unwrap()), 2);
+
+    // Functions can read old versions, whether defined in the same program
+    // or stored
+    assert_eq!(
+        run("old d = Staff@1 ? dept == d; old 'x'").await,
+        run("Staff@1").await
+    );
+    run("old d = Staff@1 ? dept == d; was d = old d; nil")
+        .await
+        .unwrap();
+    let text = fs::read_to_string(dir.join("old").join("1")).unwrap();
+    assert!(text.ends_with("old d = Staff@1 ? dept == d; old"));
+    assert_eq!(count(run("was 'x'").await.unwrap()), 2);
+
+    // Definitions are read back when the store is opened again
+    let (_, _, run) = open(&dir).await;
+    assert_eq!(count(run("staffed 'x'").await.unwrap()), 2);
+    assert_eq!(run("add 1").await, Ok(Exp::Int(2)));
+    assert_eq!(count(run("old 'x'").await.unwrap()), 2);
+}
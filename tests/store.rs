@@ -0,0 +1,281 @@
+mod common;
+
+use common::temp_dir;
+use sdb::{decode_name, encode_name, parse, Env, Error, Store, Version};
+
+use std::{collections::HashSet, fs, sync::Arc, time::Duration};
+use tokio::time::timeout;
+
+fn env(vars: &[(&str, &str)]) -> Env {
+    vars.iter()
+        .map(|(var, text)| (var.to_string(), parse(text).unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_write_read() {
+    let dir = temp_dir("write-read");
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+
+    let staff = parse("id, name : 1, 'Alice'").unwrap();
+    store.write("Staff", &staff).await.unwrap();
+    assert_eq!(store.read("Staff").await.unwrap(), staff);
+    let text = fs::read_to_string(dir.join("^staff").join("1")).unwrap();
+    assert!(text.starts_with("-- version 1, written "));
+    assert!(text.ends_with("\nid, name : 1, 'Alice'"));
+
+    let staff = parse("id, name : 1, 'Alice', 2, 'Bob'").unwrap();
+    store.write("Staff", &staff).await.unwrap();
+    assert_eq!(store.read("Staff").await.unwrap(), staff);
+
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    assert_eq!(fs::read_dir(dir.join("^staff")).unwrap().count(), 2);
+}
+
+#[tokio::test]
+async fn test_versions() {
+    let dir = temp_dir("versions");
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+
+    let versions = ["a : 1", "a : 2", "a : 3"];
+    for text in versions {
+        store.write("A", &parse(text).unwrap()).await.unwrap();
+    }
+    for (n, text) in versions.iter().enumerate() {
+        let version = Version::Number(n as u64 + 1);
+        let exp = store.read_version("A", &version).await.unwrap();
+        assert_eq!(exp, parse(text).unwrap());
+    }
+    assert_eq!(
+        store.read_version("A", &Version::Number(4)).await,
+        Err(Error::UnknownVariable("A@4".to_string()))
+    );
+
+    fs::create_dir_all(dir.join("^b")).unwrap();
+    fs::write(
+        dir.join("^b").join("1"),
+        "-- version 1, written 2026-01-01T00:00:00Z\nb : 1",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("^b").join("2"),
+        "-- version 2, written 2026-02-01T09:30:00Z\nb : 2",
+    )
+    .unwrap();
+    let at = |time: &str| Version::Time(time.to_string());
+    assert_eq!(
+        store.read_version("B", &at("2026-01-15")).await,
+        Ok(parse("b : 1").unwrap())
+    );
+    assert_eq!(
+        store.read_version("B", &at("2026-02-01")).await,
+        Ok(parse("b : 1").unwrap())
+    );
+    assert_eq!(
+        store.read_version("B", &at("2026-02-01T09:30:00Z")).await,
+        Ok(parse("b : 2").unwrap())
+    );
+    assert_eq!(
+        store.read_version("B", &at("2025-12-31")).await,
+        Err(Error::UnknownVariable("B@'2025-12-31'".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn test_interrupted_write() {
+    let dir = temp_dir("interrupted-write");
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+
+    let old = env(&[("Staff", "id, name : 1, 'Alice'")]);
+    store.write_env(&old).await.unwrap();
+
+    // A writer killed before its rename leaves a partial temporary file behind
+    fs::write(dir.join("^staff.7.tmp"), "id, name : 1, 'Alice', 2, 'Bo").unwrap();
+    assert_eq!(store.read("Staff").await.unwrap(), old["Staff"]);
+
+    // Reopening the store after the crash cleans it up
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+    assert!(!dir.join("^staff.7.tmp").exists());
+    assert_eq!(store.read("Staff").await.unwrap(), old["Staff"]);
+
+    let new = env(&[("Staff", "id, name : 1, 'Alice', 2, 'Bob'")]);
+    store.write_env(&new).await.unwrap();
+    assert_eq!(store.read("Staff").await.unwrap(), new["Staff"]);
+}
+
+#[tokio::test]
+async fn test_failed_transaction() {
+    let dir = temp_dir("failed-transaction");
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+
+    let old = env(&[("A", "a : 1")]);
+    store.write_env(&old).await.unwrap();
+
+    // Staging the second variable fails because its name is too long
+    let long = "B".repeat(300);
+    let new = env(&[("A", "a : 2"), (&long, "b : 2")]);
+    assert!(store.write_env(&new).await.is_err());
+    assert_eq!(store.read("A").await.unwrap(), old["A"]);
+
+    let leftovers = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(leftovers, vec!["^a"]);
+}
+
+#[tokio::test]
+async fn test_recovery() {
+    let dir = temp_dir("recovery");
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+
+    let old = env(&[("A", "a : 1"), ("B", "b : 1")]);
+    store.write_env(&old).await.unwrap();
+
+    // A committed transaction interrupted after installing A but not B
+    fs::write(dir.join("^a").join("2"), "a : 2").unwrap();
+    fs::write(dir.join("^b.1.tmp"), "b : 2").unwrap();
+    fs::write(
+        dir.join("transaction.2.journal"),
+        "^a.0.tmp ^a/2\n^b.1.tmp ^b/2\n",
+    )
+    .unwrap();
+
+    // An uncommitted transaction interrupted while staging
+    fs::write(dir.join("^a.3.tmp"), "a : 3").unwrap();
+
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+    assert_eq!(store.read("A").await.unwrap(), parse("a : 2").unwrap());
+    assert_eq!(store.read("B").await.unwrap(), parse("b : 2").unwrap());
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files, vec!["^a", "^b"]);
+}
+
+#[test]
+fn test_encode_name() {
+    assert_eq!(encode_name("staff"), Ok("staff".to_string()));
+    assert_eq!(encode_name("Staff"), Ok("^staff".to_string()));
+    assert_eq!(encode_name("_x_1"), Ok("_x_1".to_string()));
+    assert_eq!(encode_name("."), Ok("%2e".to_string()));
+    assert_eq!(encode_name(".."), Ok("%2e%2e".to_string()));
+    assert_eq!(
+        encode_name("../etc/passwd"),
+        Ok("%2e%2e%2fetc%2fpasswd".to_string())
+    );
+    assert_eq!(encode_name("a.tmp"), Ok("a%2etmp".to_string()));
+    assert_eq!(encode_name("con"), Ok("%63on".to_string()));
+    assert_eq!(encode_name("Con"), Ok("^con".to_string()));
+    assert_eq!(encode_name("é"), Ok("%c3%a9".to_string()));
+    assert_eq!(encode_name(""), Err(Error::InvalidName("".to_string())));
+    assert!(encode_name(&"a".repeat(300)).is_err());
+
+    // Names differing only in case must not collide on case-insensitive file systems
+    let a = encode_name("Staff").unwrap();
+    let b = encode_name("staff").unwrap();
+    assert_ne!(a.to_lowercase(), b.to_lowercase());
+}
+
+#[test]
+fn test_decode_name() {
+    for var in ["staff", "Staff", "STAFF", ".", "a/b", "con", "%", "^", "é"] {
+        assert_eq!(
+            decode_name(&encode_name(var).unwrap()),
+            Some(var.to_string())
+        );
+    }
+    assert_eq!(decode_name("Staff"), None);
+    assert_eq!(decode_name("con"), None);
+    assert_eq!(decode_name("%2E"), None);
+    assert_eq!(decode_name("^staff.0.tmp"), None);
+    assert_eq!(decode_name("%zz"), None);
+}
+
+#[tokio::test]
+async fn test_escaping_names() {
+    let dir = temp_dir("escaping-names");
+    let store = Store::new(dir.join("db"));
+    store.open().await.unwrap();
+
+    let exp = parse("a : 1").unwrap();
+    store.write("../escaped", &exp).await.unwrap();
+    assert!(!dir.join("escaped").exists());
+    assert_eq!(store.read("../escaped").await.unwrap(), exp);
+
+    store
+        .write("Staff", &parse("a : 2").unwrap())
+        .await
+        .unwrap();
+    store
+        .write("staff", &parse("a : 3").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(store.read("Staff").await.unwrap(), parse("a : 2").unwrap());
+    assert_eq!(store.read("staff").await.unwrap(), parse("a : 3").unwrap());
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_names() {
+    let dir = temp_dir("migrate-legacy-names");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("Staff"), "a : 1").unwrap();
+    fs::write(dir.join("staff"), "a : 2").unwrap();
+
+    let store = Store::new(&dir);
+    store.open().await.unwrap();
+    assert_eq!(store.read("Staff").await.unwrap(), parse("a : 1").unwrap());
+    assert_eq!(store.read("staff").await.unwrap(), parse("a : 2").unwrap());
+    assert!(!dir.join("Staff").exists());
+    assert!(dir.join("^staff").join("1").exists());
+    assert!(dir.join("staff").join("1").exists());
+}
+
+#[tokio::test]
+async fn test_locks() {
+    let store = Arc::new(Store::new(temp_dir("locks")));
+    let none = HashSet::new();
+    let x = HashSet::from(["x".to_string()]);
+
+    // Released locks are forgotten, and taking them again still excludes
+    // other programs, including one that was already waiting
+    for _ in 0..3 {
+        let write = store.lock(&none, &x).await;
+        let waiting = tokio::spawn({
+            let store = Arc::clone(&store);
+            let x = x.clone();
+            async move {
+                drop(store.lock(&x, &HashSet::new()).await);
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(write);
+        timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let read = store.lock(&x, &none).await;
+    assert!(timeout(Duration::from_millis(20), store.lock(&x, &none))
+        .await
+        .is_ok());
+    assert!(timeout(Duration::from_millis(20), store.lock(&none, &x))
+        .await
+        .is_err());
+    drop(read);
+    assert!(timeout(Duration::from_millis(20), store.lock(&none, &x))
+        .await
+        .is_ok());
+}